@@ -1,7 +1,6 @@
 use client::deserialized;
 use saltyfishie_clients as client;
-use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::Write;
 
 #[tokio::main]
 async fn main() -> Result<(), paho_mqtt::Error> {
@@ -12,11 +11,12 @@ async fn main() -> Result<(), paho_mqtt::Error> {
 }
 
 async fn run() -> Result<(), paho_mqtt::Error> {
-    // let (db_client, mut mqtt_client) = tokio::join!(make_db_client(), make_mqtt_client());
-    let (mut mqtt_client,) = tokio::join!(make_mqtt_client());
+    let (db_client, (mqtt_client, mut mqtt_receiver)) =
+        tokio::join!(make_db_client(), make_mqtt_client());
+    db_client.set_dead_letter_publisher(mqtt_client.clone());
 
     // loop {
-    if let Some(msg) = mqtt_client.poll().await {
+    if let Some(msg) = mqtt_receiver.poll().await {
         if msg.retained() {
             print!("(R) ");
         }
@@ -27,12 +27,11 @@ async fn run() -> Result<(), paho_mqtt::Error> {
             serde_json::from_slice::<serde_json::Value>(msg.payload()).unwrap()
         );
 
-        // if let Err(err) = db_client
-        //     .push(msg.topic(), std::str::from_utf8(msg.payload()).unwrap())
-        //     .await
-        // {
-        //     log::error!("db push error: {}", err)
-        // };
+        let client_id = mqtt_client.client_id();
+        let info = client::MessageInfo::from(&msg).with_client_id(&client_id);
+        if let Err(err) = db_client.push_with_info(&info, msg.payload()).await {
+            log::error!("db push error: {}", err)
+        };
     }
     Ok(())
     // }
@@ -55,69 +54,54 @@ fn setup_logger() {
         .init();
 }
 
-async fn make_mqtt_client() -> client::MqttClient {
+async fn make_mqtt_client() -> (client::MqttClient, client::MqttReceiver) {
     let source_dir = std::env::current_dir().unwrap();
     let config_file_path = source_dir.join("configs").join("mqtt_connection.json");
 
-    let file = File::open(config_file_path).unwrap();
-    let config_reader = BufReader::new(file);
     let config: deserialized::MqttClientConfig =
-        serde_json::from_reader(config_reader).expect("Failed to deserialize JSON");
+        match client::app_config::load(config_file_path, "mqtt") {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Error opening config file: {}", e);
+                std::process::exit(1);
+            }
+        };
 
     dbg!(&config);
 
     client::MqttClient::start(config.into()).await
 }
 
-// async fn make_db_client() -> client::MysqlClient {
-//     use client::{
-//         setup_config::{self, SqlServerSetupConfig},
-//         MysqlClientConfig,
-//     };
-
-//     let config: setup_config::SqlServerSetupConfig = {
-//         let source_dir = std::env::current_dir().unwrap();
-//         let config_file_path = source_dir.join("configs").join("db_connection.json");
-
-//         log::info!(
-//             "Database client configuration: \"{}\"",
-//             config_file_path.to_str().unwrap_or("{unknown}")
-//         );
-
-//         match SqlServerSetupConfig::try_from(config_file_path) {
-//             Ok(f) => f,
-//             Err(e) => {
-//                 println!("Error opening config file: {}", e);
-//                 std::process::exit(1);
-//             }
-//         }
-//     };
-
-//     let mut db_opts = sqlx::mysql::MySqlConnectOptions::new()
-//         .host(&config.host)
-//         .username(&config.username)
-//         .database(&config.database);
-
-//     match &config.password {
-//         Some(password) => {
-//             db_opts = db_opts.password(password);
-//         }
-//         None => {
-//             let password = match std::env::var("DB_PASSWORD") {
-//                 Ok(o) => o,
-//                 Err(_) => "".to_string(),
-//             };
-//             db_opts = db_opts.password(&password);
-//         }
-//     }
-
-//     if let Some(port) = &config.port {
-//         db_opts = db_opts.port(*port);
-//     }
-
-//     client::MysqlClient::start(MysqlClientConfig {
-//         connect_options: db_opts,
-//         topic_table_map: config.topic_table_map,
-//     })
-//     .await
-// }
+async fn make_db_client() -> Box<dyn client::DbClient> {
+    use client::setup_config::{self, SqlServerSetupConfig};
+
+    let mut config: setup_config::SqlServerSetupConfig = {
+        let source_dir = std::env::current_dir().unwrap();
+        let config_file_path = source_dir.join("configs").join("db_connection.json");
+
+        log::info!(
+            "Database client configuration: \"{}\"",
+            config_file_path.to_str().unwrap_or("{unknown}")
+        );
+
+        match SqlServerSetupConfig::try_from(config_file_path) {
+            Ok(f) => f,
+            Err(e) => {
+                println!("Error opening config file: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if config.password.is_none() {
+        config.password = Some(std::env::var("DB_PASSWORD").unwrap_or_default());
+    }
+
+    match client::start_client(config).await {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("Database client start error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Publishes, subscribes and sends MQTT v5 requests with the configuration
+//! files of the services, for debugging their topics.
+
+use paho_mqtt as paho;
+use saltyfishie_clients::{app_config, deserialized, MqttClient};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: mqtt-cli [OPTIONS] <COMMAND>
+
+Commands:
+  sub <FILTER>                     Print the messages matching FILTER until interrupted
+  pub <TOPIC> <PAYLOAD|@FILE>      Publish a message, @- reading the payload from stdin
+  request <TOPIC> <PAYLOAD|@FILE>  Publish a request and print its response
+
+Options:
+  --config <PATH>          MQTT client configuration [default: configs/mqtt_connection.json]
+  --format <json|raw|hex>  Output of payloads [default: json]
+  --qos <0|1|2>            Quality of service [default: 1]
+  --retain                 Publish a retained message
+  --response-topic <TOPIC> Topic of the response to a request [default: <client id>/response]
+  --timeout <MS>           Time to wait for the response to a request [default: 5000]
+  -h, --help               Print this help
+
+The configuration is loaded as by the services: TOML or YAML by extension when
+built with the toml or yaml feature, with ${VAR} interpolated and settings
+overridden by CLIENTS_MQTT__* variables. Its client id is suffixed with
+`-cli-<pid>`, so the tool does not take over the session of the service it
+configures.";
+
+/// Exit codes, as in sysexits.h.
+mod exit {
+    pub const USAGE: u8 = 64;
+    pub const NO_INPUT: u8 = 66;
+    pub const UNAVAILABLE: u8 = 69;
+    pub const TEMPFAIL: u8 = 75;
+    pub const CONFIG: u8 = 78;
+}
+
+/// How payloads are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Pretty printed, or as text if the payload is not JSON.
+    Json,
+    Raw,
+    Hex,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Sub { filter: String },
+    Pub { topic: String, payload: String },
+    Request { topic: String, payload: String },
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    command: Command,
+    config: PathBuf,
+    format: Format,
+    qos: i32,
+    retain: bool,
+    response_topic: Option<String>,
+    timeout: Duration,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut config = PathBuf::from("configs/mqtt_connection.json");
+    let mut format = Format::Json;
+    let mut qos = 1;
+    let mut retain = false;
+    let mut response_topic = None;
+    let mut timeout = Duration::from_millis(5000);
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .map(str::to_string)
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "--config" => config = value()?.into(),
+            "--format" => {
+                format = match value()?.as_str() {
+                    "json" => Format::Json,
+                    "raw" => Format::Raw,
+                    "hex" => Format::Hex,
+                    other => return Err(format!("unknown format '{}'", other)),
+                }
+            }
+            "--qos" => {
+                qos = match value()?.parse() {
+                    Ok(qos @ 0..=2) => qos,
+                    _ => return Err("--qos must be 0, 1 or 2".to_string()),
+                }
+            }
+            "--retain" => retain = true,
+            "--response-topic" => response_topic = Some(value()?),
+            "--timeout" => {
+                let ms = value()?
+                    .parse()
+                    .map_err(|_| "--timeout must be in milliseconds".to_string())?;
+                timeout = Duration::from_millis(ms);
+            }
+            "-h" | "--help" => positional = vec!["help".to_string()],
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unexpected argument '{}'", arg))
+            }
+            _ => positional.push(arg),
+        }
+        if positional.first().is_some_and(|c| c == "help") {
+            break;
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("help") => Command::Help,
+        Some("sub") => Command::Sub {
+            filter: positional.next().ok_or("sub requires a topic filter")?,
+        },
+        Some(cmd @ ("pub" | "request")) => {
+            let topic = positional
+                .next()
+                .ok_or_else(|| format!("{} requires a topic", cmd))?;
+            let payload = positional
+                .next()
+                .ok_or_else(|| format!("{} requires a payload", cmd))?;
+            if cmd == "pub" {
+                Command::Pub { topic, payload }
+            } else {
+                Command::Request { topic, payload }
+            }
+        }
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument '{}'", extra));
+    }
+
+    Ok(Args {
+        command,
+        config,
+        format,
+        qos,
+        retain,
+        response_topic,
+        timeout,
+    })
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(exit::USAGE);
+        }
+    };
+    if args.command == Command::Help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime.block_on(run(args)),
+        Err(e) => {
+            eprintln!("error: failed to start the runtime: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> ExitCode {
+    let config: deserialized::MqttClientConfig = match app_config::load(&args.config, "mqtt") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::from(exit::CONFIG);
+        }
+    };
+    let client_id = format!("{}-cli-{}", config.client_id(), std::process::id());
+    let config = config.with_client_id(client_id).without_subscriptions();
+
+    let (client, mut receiver) = tokio::select! {
+        started = MqttClient::start(config.into()) => started,
+        _ = tokio::signal::ctrl_c() => return ExitCode::SUCCESS,
+    };
+
+    let code = match &args.command {
+        Command::Sub { filter } => subscribe(&client, &mut receiver, filter, &args).await,
+        Command::Pub { topic, payload } => match read_payload(payload) {
+            Ok(payload) => publish(&client, topic, payload, &args).await,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::from(exit::NO_INPUT)
+            }
+        },
+        Command::Request { topic, payload } => match read_payload(payload) {
+            Ok(payload) => request(&client, &mut receiver, topic, payload, &args).await,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::from(exit::NO_INPUT)
+            }
+        },
+        Command::Help => ExitCode::SUCCESS,
+    };
+
+    let _ = client.disconnect().await;
+    code
+}
+
+async fn subscribe(
+    client: &MqttClient,
+    receiver: &mut saltyfishie_clients::MqttReceiver,
+    filter: &str,
+    args: &Args,
+) -> ExitCode {
+    if let Err(e) = client.subscribe(filter, args.qos).await {
+        eprintln!("error: failed to subscribe to '{}': {}", filter, e);
+        return ExitCode::from(exit::UNAVAILABLE);
+    }
+
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.poll() => msg,
+            _ = tokio::signal::ctrl_c() => return ExitCode::SUCCESS,
+        };
+        // `None` on disconnection, polling again reconnects.
+        let Some(msg) = msg else {
+            continue;
+        };
+        if let Err(e) = print_message(&msg, args.format) {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+async fn publish(client: &MqttClient, topic: &str, payload: Vec<u8>, args: &Args) -> ExitCode {
+    let msg = paho::MessageBuilder::new()
+        .topic(topic)
+        .payload(payload)
+        .qos(args.qos)
+        .retained(args.retain)
+        .finalize();
+
+    match client.publish(msg).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: failed to publish to '{}': {}", topic, e);
+            ExitCode::from(exit::UNAVAILABLE)
+        }
+    }
+}
+
+/// Publishes a request with a response topic and correlation data, then
+/// prints the first message on the response topic, skipping those
+/// correlated with another request.
+async fn request(
+    client: &MqttClient,
+    receiver: &mut saltyfishie_clients::MqttReceiver,
+    topic: &str,
+    payload: Vec<u8>,
+    args: &Args,
+) -> ExitCode {
+    let response_topic = args
+        .response_topic
+        .clone()
+        .unwrap_or_else(|| format!("{}/response", client.client_id()));
+    let correlation = correlation_data();
+
+    if let Err(e) = client.subscribe(&response_topic, args.qos).await {
+        eprintln!("error: failed to subscribe to '{}': {}", response_topic, e);
+        return ExitCode::from(exit::UNAVAILABLE);
+    }
+
+    let mut props = paho::Properties::new();
+    let pushed = props
+        .push_string(paho::PropertyCode::ResponseTopic, &response_topic)
+        .and_then(|_| props.push_binary(paho::PropertyCode::CorrelationData, correlation.clone()));
+    if let Err(e) = pushed {
+        eprintln!("error: invalid request properties: {}", e);
+        return ExitCode::from(exit::USAGE);
+    }
+    let msg = paho::MessageBuilder::new()
+        .topic(topic)
+        .payload(payload)
+        .qos(args.qos)
+        .properties(props)
+        .finalize();
+    if let Err(e) = client.publish(msg).await {
+        eprintln!("error: failed to publish to '{}': {}", topic, e);
+        return ExitCode::from(exit::UNAVAILABLE);
+    }
+
+    let response = tokio::time::timeout(args.timeout, async {
+        loop {
+            let Some(msg) = receiver.poll().await else {
+                continue;
+            };
+            let correlated = msg
+                .properties()
+                .get_binary(paho::PropertyCode::CorrelationData)
+                .is_none_or(|data| data == correlation);
+            if msg.topic() == response_topic && correlated {
+                return msg;
+            }
+        }
+    });
+
+    tokio::select! {
+        response = response => match response {
+            Ok(msg) => match print_message(&msg, args.format) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ExitCode::FAILURE
+                }
+            },
+            Err(_) => {
+                eprintln!("error: no response on '{}' within {:?}", response_topic, args.timeout);
+                ExitCode::from(exit::TEMPFAIL)
+            }
+        },
+        _ = tokio::signal::ctrl_c() => ExitCode::SUCCESS,
+    }
+}
+
+/// Unique enough to tell the responses of concurrent requests apart.
+fn correlation_data() -> Vec<u8> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{}-{}", std::process::id(), nanos).into_bytes()
+}
+
+/// Prints the topic, marked `(R)` if retained, then the payload.
+fn print_message(msg: &paho::Message, format: Format) -> std::io::Result<()> {
+    let mut out = std::io::stdout().lock();
+    if msg.retained() {
+        write!(out, "(R) ")?;
+    }
+    writeln!(out, "{}", msg.topic())?;
+    out.write_all(&format_payload(msg.payload(), format))?;
+    writeln!(out)?;
+    out.flush()
+}
+
+fn format_payload(payload: &[u8], format: Format) -> Vec<u8> {
+    match format {
+        Format::Raw => payload.to_vec(),
+        Format::Hex => payload
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+            .into_bytes(),
+        Format::Json => match serde_json::from_slice::<serde_json::Value>(payload) {
+            Ok(value) => serde_json::to_vec_pretty(&value).unwrap_or_default(),
+            Err(_) => String::from_utf8_lossy(payload).into_owned().into_bytes(),
+        },
+    }
+}
+
+/// The payload argument, or the content of the file it names after `@`,
+/// stdin for `@-`.
+fn read_payload(arg: &str) -> std::io::Result<Vec<u8>> {
+    match arg.strip_prefix('@') {
+        Some("-") => {
+            let mut payload = Vec::new();
+            std::io::stdin().read_to_end(&mut payload)?;
+            Ok(payload)
+        }
+        Some(path) => std::fs::read(path),
+        None => Ok(arg.as_bytes().to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_commands() {
+        let args = parse(&["--format", "hex", "pub", "a/b", "@msg.json", "--retain"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Pub {
+                topic: "a/b".into(),
+                payload: "@msg.json".into()
+            }
+        );
+        assert_eq!(args.format, Format::Hex);
+        assert!(args.retain);
+
+        let args = parse(&["sub", "sensors/#", "--qos=0"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Sub {
+                filter: "sensors/#".into()
+            }
+        );
+        assert_eq!(args.qos, 0);
+
+        assert_eq!(parse(&["sub"]).unwrap_err(), "sub requires a topic filter");
+        assert_eq!(
+            parse(&["--qos", "3", "sub", "a"]).unwrap_err(),
+            "--qos must be 0, 1 or 2"
+        );
+        assert_eq!(parse(&["--help"]).unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn formats_payloads() {
+        assert_eq!(format_payload(b"\x00\xff", Format::Hex), b"00ff");
+        assert_eq!(
+            format_payload(br#"{"a":1}"#, Format::Json),
+            b"{\n  \"a\": 1\n}"
+        );
+        assert_eq!(format_payload(b"not json", Format::Json), b"not json");
+        assert_eq!(format_payload(b"raw", Format::Raw), b"raw");
+    }
+}
@@ -0,0 +1,331 @@
+//! Forwards the messages of the configured MQTT subscriptions to the tables
+//! mapped to their topics, and publishes the new rows of the tables of the
+//! `publisher` section, until stopped by SIGINT or SIGTERM.
+
+// Only the usage is left without a database driver.
+#![cfg_attr(
+    not(any(feature = "mysql", feature = "postgres", feature = "sqlite")),
+    allow(dead_code)
+)]
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: mqtt-sql-forwarder [OPTIONS]
+
+Options:
+  --config <PATH>       Configuration with `mqtt`, `database` and `forwarding`
+                        sections, repeated to merge several files, later
+                        ones taking precedence. Replaces the two below.
+  --mqtt-config <PATH>  MQTT client configuration [default: configs/mqtt_connection.json]
+  --db-config <PATH>    Database client configuration [default: configs/db_connection.json]
+  --journal <PATH>      Journal of the messages not yet written, that of the
+                        `forwarding` section if any [default: mqtt-sql-forwarder.journal]
+  -h, --help            Print this help
+  -V, --version         Print the version
+
+Strings of the configuration may refer to environment variables as ${VAR} or
+${VAR:-default}, and variables such as CLIENTS_MQTT__BROKER_URI or
+CLIENTS_DATABASE__HOST override the settings at that path.
+Configuration files are TOML (.toml) or YAML (.yaml, .yml) when built with the
+toml or yaml feature, and JSON otherwise.
+The database password is read from DB_PASSWORD if the configuration has none.
+Logging is configured with RUST_LOG, `info` by default.";
+
+/// Exit codes, as in sysexits.h.
+mod exit {
+    pub const USAGE: u8 = 64;
+    /// The database could not be connected to.
+    pub const UNAVAILABLE: u8 = 69;
+    /// Messages are left in the journal or the spool, to be written at the
+    /// next start.
+    pub const TEMPFAIL: u8 = 75;
+    pub const CONFIG: u8 = 78;
+}
+
+struct Args {
+    /// Files of an `AppConfig`, used instead of the two below if any.
+    config: Vec<PathBuf>,
+    mqtt_config: PathBuf,
+    db_config: PathBuf,
+    journal: Option<PathBuf>,
+}
+
+enum Command {
+    Run(Args),
+    Help,
+    Version,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut out = Args {
+        config: Vec::new(),
+        mqtt_config: PathBuf::from("configs/mqtt_connection.json"),
+        db_config: PathBuf::from("configs/db_connection.json"),
+        journal: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .map(str::to_string)
+                .or_else(|| args.next())
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+        match flag.as_str() {
+            "--config" => out.config.push(value()?),
+            "--mqtt-config" => out.mqtt_config = value()?,
+            "--db-config" => out.db_config = value()?,
+            "--journal" => out.journal = Some(value()?),
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    Ok(Command::Run(out))
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Run(args)) => run(args),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        Ok(Command::Version) => {
+            println!("mqtt-sql-forwarder {}", env!("CARGO_PKG_VERSION"));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            ExitCode::from(exit::USAGE)
+        }
+    }
+}
+
+#[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+fn run(_: Args) -> ExitCode {
+    log::error!("Built without any database driver, enable the mysql, postgres or sqlite feature");
+    ExitCode::from(exit::CONFIG)
+}
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+fn run(args: Args) -> ExitCode {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Failed to start the runtime: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    runtime.block_on(forward(args))
+}
+
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+async fn forward(args: Args) -> ExitCode {
+    use saltyfishie_clients::TablePublisher;
+    use saltyfishie_clients::{self as clients, Forwarder};
+
+    let (mqtt_config, mut db_config, journal) = match load_configs(&args) {
+        Ok(configs) => configs,
+        Err(e) => {
+            log::error!("{}", e);
+            return ExitCode::from(exit::CONFIG);
+        }
+    };
+    if db_config.password.is_none() {
+        db_config.password = std::env::var("DB_PASSWORD").ok();
+    }
+
+    let publisher_config = db_config.publisher.clone();
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let db = tokio::select! {
+        db = clients::start_client(db_config) => match db {
+            Ok(db) => db,
+            Err(e @ clients::DbClientError::DriverDisabled(_)) => {
+                log::error!("{}", e);
+                return ExitCode::from(exit::CONFIG);
+            }
+            Err(e) => {
+                log::error!("Failed to start the database client: {}", e);
+                return ExitCode::from(exit::UNAVAILABLE);
+            }
+        },
+        _ = &mut shutdown => return ExitCode::SUCCESS,
+    };
+
+    let mut forwarder = Forwarder::new(db.as_ref(), &journal);
+    match forwarder.recover().await {
+        Ok(report) if report.replayed + report.failed > 0 => log::info!(
+            "Recovered {} journaled messages, {} failed",
+            report.replayed,
+            report.failed
+        ),
+        Ok(_) => {}
+        Err(e) => {
+            log::error!("Failed to recover '{}': {}", journal.display(), e);
+            return ExitCode::from(exit::TEMPFAIL);
+        }
+    }
+
+    let (mqtt, mut receiver) = tokio::select! {
+        started = clients::MqttClient::start(mqtt_config.into()) => started,
+        _ = &mut shutdown => return ExitCode::SUCCESS,
+    };
+    db.set_dead_letter_publisher(mqtt.clone());
+
+    let publisher = publisher_config
+        .map(|config| TablePublisher::new(db.as_ref(), mqtt.clone(), config))
+        .transpose();
+    let mut publisher = match publisher {
+        Ok(publisher) => publisher,
+        Err(e) => {
+            log::error!("Failed to start the publisher: {}", e);
+            return ExitCode::from(exit::CONFIG);
+        }
+    };
+    let publish = async {
+        match &mut publisher {
+            Some(publisher) => publisher.run().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    // Not dropped while forwarding a message, which would leave it to the
+    // next start.
+    let stop = async {
+        tokio::select! {
+            _ = publish => {}
+            _ = &mut shutdown => log::info!("Shutting down"),
+        }
+    };
+    forwarder.run(&mut receiver, stop).await;
+
+    // Stop receiving first, then forward what was received in the meantime,
+    // so that nothing is acknowledged but not written.
+    if let Err(e) = mqtt.disconnect().await {
+        log::warn!("Failed to disconnect from the broker: {}", e);
+    }
+    forwarder.forward_received(&mut receiver).await;
+    match forwarder.flush().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!(
+                "{} messages left to write at the next start: {}",
+                forwarder.pending(),
+                e
+            );
+            ExitCode::from(exit::TEMPFAIL)
+        }
+    }
+}
+
+/// The MQTT and database configurations, and the journal path.
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+fn load_configs(
+    args: &Args,
+) -> Result<
+    (
+        saltyfishie_clients::deserialized::MqttClientConfig,
+        saltyfishie_clients::setup_config::SqlServerSetupConfig,
+        PathBuf,
+    ),
+    saltyfishie_clients::app_config::ConfigError,
+> {
+    use saltyfishie_clients::app_config;
+
+    let default_journal = || PathBuf::from("mqtt-sql-forwarder.journal");
+
+    if args.config.is_empty() {
+        let journal = args.journal.clone().unwrap_or_else(default_journal);
+        return Ok((
+            app_config::load(&args.mqtt_config, "mqtt")?,
+            app_config::load(&args.db_config, "database")?,
+            journal,
+        ));
+    }
+
+    let mut config = app_config::AppConfig::load_merged(&args.config)?;
+    let journal = args
+        .journal
+        .clone()
+        .or(config.forwarding.journal.take())
+        .unwrap_or_else(default_journal);
+    Ok((config.require_mqtt()?, config.require_database()?, journal))
+}
+
+/// Completes on SIGINT, or on SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => log::warn!("Cannot handle SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Cannot handle SIGINT: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_flags() {
+        let Ok(Command::Run(args)) = parse(&["--db-config", "db.json", "--journal=j.jsonl"]) else {
+            panic!("expected a run");
+        };
+        assert_eq!(
+            args.mqtt_config,
+            PathBuf::from("configs/mqtt_connection.json")
+        );
+        assert_eq!(args.db_config, PathBuf::from("db.json"));
+        assert_eq!(args.journal, Some(PathBuf::from("j.jsonl")));
+        assert!(args.config.is_empty());
+
+        let Ok(Command::Run(args)) = parse(&["--config", "base.json", "--config=prod.json"]) else {
+            panic!("expected a run");
+        };
+        assert_eq!(
+            args.config,
+            [PathBuf::from("base.json"), PathBuf::from("prod.json")]
+        );
+        assert_eq!(args.journal, None);
+
+        assert!(matches!(parse(&["-h"]), Ok(Command::Help)));
+        assert_eq!(
+            parse(&["--db-config"]).err().unwrap(),
+            "--db-config requires a value"
+        );
+        assert_eq!(
+            parse(&["db.json"]).err().unwrap(),
+            "unexpected argument 'db.json'"
+        );
+    }
+}
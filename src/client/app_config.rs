@@ -0,0 +1,769 @@
+//! Loading of configuration files, and the [`AppConfig`] holding every
+//! section of a service in one file, or in several merged.
+//!
+//! The format of a file is that of its extension: `.toml` with the `toml`
+//! feature, `.yaml` or `.yml` with the `yaml` feature, and JSON otherwise.
+//! Every format deserializes into the same structs.
+//!
+//! Strings may refer to environment variables, `${VAR}` being replaced by
+//! the value of `VAR` and `${VAR:-default}` by `default` if `VAR` is unset
+//! or empty, while `$${` is a literal `${`.
+//!
+//! Settings are then overridden by the variables named after their path,
+//! with the prefix [`ENV_PREFIX`] and sections separated by `__`. For
+//! example, `CLIENTS_MQTT__BROKER_URI` overrides the `broker_uri` of the
+//! `mqtt` section. A value replacing text stays text. Other values are read
+//! as JSON, e.g. `5432`, `true` or `{"mode": "required"}`, and as text if
+//! they are not JSON. Where the configuration expects text, numbers and
+//! booleans are read as the text they were written as, so that
+//! `CLIENTS_DATABASE__PASSWORD=12345` sets the password `12345` whether the
+//! file has one or not.
+
+use crate::deserialized::MqttClientConfig;
+use crate::setup_config::{sql_server, SqlServerSetupConfig};
+use serde::de::value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{IntoDeserializer, Visitor};
+use serde::Deserializer;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the environment variables overriding settings.
+pub const ENV_PREFIX: &str = "CLIENTS_";
+
+/// Configuration of a whole service.
+///
+/// ```json
+/// {
+///     "mqtt": {"client_id": "forwarder", "broker_uri": "mqtt://localhost:1883", "subscriptions": {}},
+///     "database": {"driver": "postgres", "host": "localhost", "username": "forwarder", "database": "telemetry"},
+///     "forwarding": {"rules": {"sensors/temp": "temperatures"}}
+/// }
+/// ```
+#[derive(Debug, serde::Deserialize)]
+pub struct AppConfig {
+    pub mqtt: Option<MqttClientConfig>,
+    /// Includes the rules of the forwarding section in its
+    /// `topic_table_map`.
+    pub database: Option<SqlServerSetupConfig>,
+    #[serde(default)]
+    pub forwarding: Forwarding,
+}
+
+/// How messages are forwarded from the broker to the database.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Forwarding {
+    /// Tables the payloads of each topic are written to, added to those of
+    /// the database section, which take precedence.
+    pub rules: sql_server::TopicTableMapping,
+    /// Journal of the messages not yet written, see [`crate::Forwarder`].
+    pub journal: Option<PathBuf>,
+}
+
+/// Why a configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The file is not valid in its format.
+    Parse {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The format of the file is that of a disabled feature.
+    FormatDisabled {
+        path: PathBuf,
+        feature: &'static str,
+    },
+    /// The settings, once merged, do not match the configuration.
+    Invalid(serde_json::Error),
+    /// A string of the file refers to an unset variable, or is malformed.
+    Interpolation { path: PathBuf, reason: String },
+    /// The variable overrides a setting inside a value that is not a section.
+    Override { variable: String },
+    /// A section required by the caller is absent.
+    MissingSection(&'static str),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "cannot read '{}': {}", path.display(), source)
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "cannot parse '{}': {}", path.display(), source)
+            }
+            ConfigError::FormatDisabled { path, feature } => write!(
+                f,
+                "cannot parse '{}': built without the {} feature",
+                path.display(),
+                feature
+            ),
+            ConfigError::Interpolation { path, reason } => {
+                write!(f, "cannot interpolate '{}': {}", path.display(), reason)
+            }
+            ConfigError::Override { variable } => {
+                write!(
+                    f,
+                    "cannot apply {}: a setting on its path is not a section",
+                    variable
+                )
+            }
+            ConfigError::Invalid(e) => write!(f, "invalid configuration: {}", e),
+            ConfigError::MissingSection(section) => {
+                write!(f, "the configuration has no '{}' section", section)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source.as_ref()),
+            ConfigError::Invalid(source) => Some(source),
+            ConfigError::FormatDisabled { .. }
+            | ConfigError::Interpolation { .. }
+            | ConfigError::Override { .. }
+            | ConfigError::MissingSection(_) => None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Loads the configuration in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::load_merged([path])
+    }
+
+    /// Loads the configuration merged from the files at `paths`, the
+    /// settings of each file replacing those of the previous ones, e.g. a
+    /// base file then one per environment. Objects are merged key by key,
+    /// any other value is replaced whole. The environment overrides them
+    /// all.
+    pub fn load_merged<P: AsRef<Path>>(
+        paths: impl IntoIterator<Item = P>,
+    ) -> Result<Self, ConfigError> {
+        let mut merged = Value::Object(Default::default());
+        for path in paths {
+            merge(&mut merged, read_value(path.as_ref())?);
+        }
+        apply_overrides(&mut merged, &[], std::env::vars())?;
+        Self::from_value(merged)
+    }
+
+    pub fn from_value(value: Value) -> Result<Self, ConfigError> {
+        let mut config: AppConfig = deserialize(value)?;
+        if let Some(database) = &mut config.database {
+            for (topic, mapping) in &config.forwarding.rules {
+                database
+                    .topic_table_map
+                    .entry(topic.clone())
+                    .or_insert_with(|| mapping.clone());
+            }
+        }
+        Ok(config)
+    }
+
+    /// The MQTT section, which the caller requires.
+    pub fn require_mqtt(&mut self) -> Result<MqttClientConfig, ConfigError> {
+        self.mqtt.take().ok_or(ConfigError::MissingSection("mqtt"))
+    }
+
+    /// The database section, which the caller requires.
+    pub fn require_database(&mut self) -> Result<SqlServerSetupConfig, ConfigError> {
+        self.database
+            .take()
+            .ok_or(ConfigError::MissingSection("database"))
+    }
+}
+
+/// Loads the file at `path` as `T`, the settings of `section` of an
+/// [`AppConfig`], e.g. `"database"`, whose variables override them.
+pub fn load<T: serde::de::DeserializeOwned>(
+    path: impl AsRef<Path>,
+    section: &str,
+) -> Result<T, ConfigError> {
+    let mut value = read_value(path.as_ref())?;
+    apply_overrides(&mut value, &[section], std::env::vars())?;
+    deserialize(value)
+}
+
+fn deserialize<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, ConfigError> {
+    T::deserialize(Lenient(value)).map_err(ConfigError::Invalid)
+}
+
+/// Format of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// The format of the file at `path`, by its extension.
+    pub fn of(path: &Path) -> Format {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => Format::Toml,
+            "yaml" | "yml" => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+
+    /// Parses `text` into the settings it holds.
+    fn parse(self, text: &str) -> Result<Value, ParseError> {
+        match self {
+            Format::Json => Ok(serde_json::from_str(text)?),
+            #[cfg(feature = "toml")]
+            Format::Toml => Ok(toml::from_str(text)?),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => Ok(serde_yaml::from_str(text)?),
+            #[allow(unreachable_patterns)]
+            _ => Err(ParseError::Disabled(self.feature())),
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Toml => "toml",
+            Format::Yaml => "yaml",
+        }
+    }
+}
+
+enum ParseError {
+    Invalid(Box<dyn std::error::Error + Send + Sync>),
+    Disabled(&'static str),
+}
+
+impl<E: std::error::Error + Send + Sync + 'static> From<E> for ParseError {
+    fn from(e: E) -> Self {
+        ParseError::Invalid(Box::new(e))
+    }
+}
+
+fn read_value(path: &Path) -> Result<Value, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut value = Format::of(path).parse(&text).map_err(|e| match e {
+        ParseError::Invalid(source) => ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        },
+        ParseError::Disabled(feature) => ConfigError::FormatDisabled {
+            path: path.to_path_buf(),
+            feature,
+        },
+    })?;
+    interpolate(&mut value, &|name| std::env::var(name).ok()).map_err(|reason| {
+        ConfigError::Interpolation {
+            path: path.to_path_buf(),
+            reason,
+        }
+    })?;
+    Ok(value)
+}
+
+/// Replaces the variables in every string of `value`, looked up with `env`.
+fn interpolate(value: &mut Value, env: &dyn Fn(&str) -> Option<String>) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains("${") => *text = interpolate_str(text, env)?,
+        Value::Array(values) => {
+            for value in values {
+                interpolate(value, env)?;
+            }
+        }
+        Value::Object(values) => {
+            for value in values.values_mut() {
+                interpolate(value, env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(text: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("$${") {
+            out.push_str("${");
+            rest = &rest[3..];
+            continue;
+        }
+        if !rest.starts_with("${") {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let Some(len) = rest.find('}') else {
+            return Err(format!("unterminated '${{' in \"{}\"", text));
+        };
+        let reference = &rest[2..len];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() {
+            return Err(format!("missing variable name in \"{}\"", text));
+        }
+        match (env(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => return Err(format!("{} is not set", name)),
+        }
+        rest = &rest[len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Overrides the settings of `value`, those of `section` in an
+/// [`AppConfig`], with the variables of `vars` named after them.
+fn apply_overrides(
+    value: &mut Value,
+    section: &[&str],
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<(), ConfigError> {
+    let mut overrides: Vec<_> = vars
+        .into_iter()
+        .filter_map(|(variable, setting)| {
+            let path: Vec<_> = variable
+                .strip_prefix(ENV_PREFIX)?
+                .split("__")
+                .map(str::to_ascii_lowercase)
+                .collect();
+            let inside = path.len() > section.len()
+                && path.iter().zip(section).all(|(key, name)| key == name);
+            let path = path[section.len().min(path.len())..].to_vec();
+            (inside && path.iter().all(|key| !key.is_empty())).then_some((variable, path, setting))
+        })
+        .collect();
+    // Sections first, so that a variable setting one inside is not lost.
+    overrides.sort_by_key(|(_, path, _)| path.len());
+
+    for (variable, path, setting) in overrides {
+        let (last, sections) = path.split_last().expect("paths are not empty");
+        let mut target = &mut *value;
+        for key in sections {
+            let Value::Object(values) = target else {
+                return Err(ConfigError::Override { variable });
+            };
+            target = values
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Default::default()));
+        }
+        let Value::Object(values) = target else {
+            return Err(ConfigError::Override { variable });
+        };
+        let target = values.entry(last.clone()).or_insert(Value::Null);
+        *target = override_value(target, setting);
+    }
+    Ok(())
+}
+
+/// The value of `setting` replacing `existing`: text if it replaces text,
+/// and JSON otherwise when it is. An absent setting may be text, so it is
+/// only set to a number or a boolean written as JSON writes it back, which
+/// [`Lenient`] then reads as that same text where text is expected.
+fn override_value(existing: &Value, setting: String) -> Value {
+    let parsed = serde_json::from_str::<Value>(&setting);
+    match (existing, parsed) {
+        (Value::String(_), _) | (_, Err(_)) => Value::String(setting),
+        (Value::Null, Ok(parsed)) => match parsed {
+            Value::Object(_) | Value::Array(_) => parsed,
+            Value::Number(_) | Value::Bool(_) if written_as_json(&parsed, &setting) => parsed,
+            _ => Value::String(setting),
+        },
+        (_, Ok(parsed)) => parsed,
+    }
+}
+
+fn written_as_json(value: &Value, setting: &str) -> bool {
+    serde_json::to_string(value).is_ok_and(|json| json == setting)
+}
+
+/// Merges `other` into `base`, key by key for objects.
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Deserializes settings like [`Value`] does, but reads numbers and
+/// booleans as text where text is expected.
+struct Lenient(Value);
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for Lenient {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for Lenient {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(values) => {
+                let mut seq = SeqDeserializer::new(values.into_iter().map(Lenient));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Object(values) => {
+                let mut map = MapDeserializer::new(
+                    values.into_iter().map(|(key, value)| (key, Lenient(value))),
+                );
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Number(n) => visitor.visit_string(n.to_string()),
+            Value::Bool(b) => visitor.visit_string(b.to_string()),
+            value => Lenient(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(Lenient(value)),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Object(values) => MapAccessDeserializer::new(MapDeserializer::new(
+                values.into_iter().map(|(key, value)| (key, Lenient(value))),
+            ))
+            .deserialize_enum(name, variants, visitor),
+            value => value.deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_objects_key_by_key() {
+        let mut base = json!({
+            "database": {
+                "host": "localhost",
+                "database": "telemetry",
+                "port": 3306,
+                "topic_table_map": {"a": "t_a"},
+            },
+            "forwarding": {"rules": {"b": "t_b"}},
+        });
+        merge(
+            &mut base,
+            json!({"database": {"host": "db.prod", "topic_table_map": {"c": "t_c"}}}),
+        );
+        assert_eq!(
+            base,
+            json!({
+                "database": {
+                    "host": "db.prod",
+                    "database": "telemetry",
+                    "port": 3306,
+                    "topic_table_map": {"a": "t_a", "c": "t_c"},
+                },
+                "forwarding": {"rules": {"b": "t_b"}},
+            })
+        );
+
+        let mut config = AppConfig::from_value(base).unwrap();
+        let database = config.require_database().unwrap();
+        let mut topics: Vec<_> = database.topic_table_map.keys().cloned().collect();
+        topics.sort();
+        assert_eq!(topics, ["a", "b", "c"]);
+        assert_eq!(database.host, "db.prod");
+        assert!(matches!(
+            config.require_mqtt(),
+            Err(ConfigError::MissingSection("mqtt"))
+        ));
+    }
+
+    #[test]
+    fn reports_the_failing_file() {
+        let path = std::env::temp_dir().join(format!("app-config-{}.json", std::process::id()));
+        std::fs::write(&path, "{\"database\": ").unwrap();
+
+        let err = AppConfig::load(&path).unwrap_err();
+        assert!(matches!(&err, ConfigError::Parse { path: p, .. } if *p == path));
+        assert!(matches!(
+            AppConfig::load(path.with_extension("missing")),
+            Err(ConfigError::Io { .. })
+        ));
+
+        std::fs::write(&path, "{\"database\": {\"port\": \"x\"}}").unwrap();
+        assert!(matches!(
+            AppConfig::load(&path),
+            Err(ConfigError::Invalid(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_retain_handling() {
+        let path = std::env::temp_dir().join(format!("app-config-rh-{}.json", std::process::id()));
+        let options = |retain_handling| {
+            json!({
+                "client_id": "c",
+                "broker_uri": "tcp://localhost:1883",
+                "subscriptions": [{"t": {"qos": 1, "options": {
+                    "no_local": false,
+                    "retain_as_publish": false,
+                    "retain_handling": retain_handling,
+                }}}],
+            })
+            .to_string()
+        };
+
+        std::fs::write(&path, options(2)).unwrap();
+        assert!(crate::setup_config::MqttClientOptions::try_from(path.clone()).is_ok());
+        std::fs::write(&path, options(3)).unwrap();
+        assert!(matches!(
+            crate::setup_config::MqttClientOptions::try_from(path.clone()),
+            Err(ConfigError::Invalid(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn detects_formats_by_extension() {
+        assert_eq!(Format::of(Path::new("db.toml")), Format::Toml);
+        assert_eq!(Format::of(Path::new("db.YML")), Format::Yaml);
+        assert_eq!(Format::of(Path::new("db.yaml")), Format::Yaml);
+        assert_eq!(Format::of(Path::new("db.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("db")), Format::Json);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn parses_toml() {
+        let text = r#"
+            # Comments are the point.
+            [database]
+            driver = "postgres"
+            database = "telemetry"
+
+            [database.topic_table_map."sensors/temp"]
+            table = "temperatures"
+        "#;
+        assert_eq!(
+            Format::Toml.parse(text).ok().unwrap(),
+            json!({"database": {
+                "driver": "postgres",
+                "database": "telemetry",
+                "topic_table_map": {"sensors/temp": {"table": "temperatures"}},
+            }})
+        );
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn parses_yaml() {
+        let text = "
+            # Comments are the point.
+            database:
+              driver: postgres
+              port: 5432
+              topic_table_map:
+                sensors/temp: temperatures
+        ";
+        assert_eq!(
+            Format::Yaml.parse(text).ok().unwrap(),
+            json!({"database": {
+                "driver": "postgres",
+                "port": 5432,
+                "topic_table_map": {"sensors/temp": "temperatures"},
+            }})
+        );
+    }
+
+    #[test]
+    fn interpolates_variables() {
+        let env = |name: &str| match name {
+            "DB_HOST" => Some("db.internal".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let mut value = json!({
+            "database": {
+                "host": "${DB_HOST}",
+                "username": "${DB_USER:-forwarder}",
+                "database": "${EMPTY:-telemetry}_${EMPTY}",
+                "topic_table_map": {"$SYS/load": "t_${DB_HOST}"},
+            },
+            "tags": ["$${DB_HOST}", "cost: $5"],
+            "port": 5432,
+        });
+        interpolate(&mut value, &env).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "database": {
+                    "host": "db.internal",
+                    "username": "forwarder",
+                    "database": "telemetry_",
+                    "topic_table_map": {"$SYS/load": "t_db.internal"},
+                },
+                "tags": ["${DB_HOST}", "cost: $5"],
+                "port": 5432,
+            })
+        );
+
+        assert_eq!(
+            interpolate_str("${DB_PASSWORD}", &env).unwrap_err(),
+            "DB_PASSWORD is not set"
+        );
+        assert!(interpolate_str("${DB_HOST", &env).is_err());
+        assert!(interpolate_str("${:-x}", &env).is_err());
+    }
+
+    #[test]
+    fn overrides_settings_from_variables() {
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let mut value = json!({
+            "mqtt": {"broker_uri": "tcp://localhost:1883", "client_id": "dev"},
+            "database": {"host": "localhost", "port": 3306},
+        });
+        apply_overrides(
+            &mut value,
+            &[],
+            vars(&[
+                ("CLIENTS_MQTT__BROKER_URI", "ssl://broker.prod:8883"),
+                ("CLIENTS_MQTT__CLIENT_ID", "1234"),
+                ("CLIENTS_DATABASE__PORT", "5432"),
+                ("CLIENTS_DATABASE__POOL__MAX_CONNECTIONS", "20"),
+                ("CLIENTS_DATABASE__PASSWORD", "12345"),
+                ("CLIENTS_DATABASE__SSL", r#"{"mode": "required"}"#),
+                ("CLIENTS_DATABASE__SSL__CA", "/etc/ca.pem"),
+                ("OTHER_DATABASE__HOST", "ignored"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "mqtt": {"broker_uri": "ssl://broker.prod:8883", "client_id": "1234"},
+                "database": {
+                    "host": "localhost",
+                    "port": 5432,
+                    "pool": {"max_connections": 20},
+                    "password": 12345,
+                    "ssl": {"mode": "required", "ca": "/etc/ca.pem"},
+                },
+            })
+        );
+        value["mqtt"]["subscriptions"] = json!({});
+        value["database"]["database"] = json!("telemetry");
+        let mut config = AppConfig::from_value(value).unwrap();
+        assert_eq!(config.require_mqtt().unwrap().client_id, "1234");
+        let database = config.require_database().unwrap();
+        assert_eq!(database.pool.max_connections, Some(20));
+        assert_eq!(database.password.as_deref(), Some("12345"));
+
+        // Absent settings written unlike JSON are kept as text.
+        let mut value = json!({});
+        apply_overrides(
+            &mut value,
+            &[],
+            vars(&[
+                ("CLIENTS_DATABASE__PASSWORD", "1.50"),
+                ("CLIENTS_DATABASE__USERNAME", "null"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({"database": {"password": "1.50", "username": "null"}})
+        );
+
+        // The file of a single section.
+        let mut value = json!({"host": "localhost"});
+        apply_overrides(
+            &mut value,
+            &["database"],
+            vars(&[
+                ("CLIENTS_DATABASE__HOST", "db.prod"),
+                ("CLIENTS_MQTT__CLIENT_ID", "prod"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(value, json!({"host": "db.prod"}));
+
+        assert!(matches!(
+            apply_overrides(
+                &mut value,
+                &["database"],
+                vars(&[("CLIENTS_DATABASE__HOST__NAME", "db")]),
+            ),
+            Err(ConfigError::Override { variable }) if variable == "CLIENTS_DATABASE__HOST__NAME"
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+#[cfg(feature = "mysql")]
+mod mysql;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use super::dialect::Dialect;
+use super::schema::ColumnType;
+use super::SqlValue;
+use crate::setup_config::{sql_server, SqlServerSetupConfig};
+use futures_util::future::BoxFuture;
+use serde_json::{Map, Value};
+
+pub type ConnectOptions<B> = <<B as sqlx::Database>::Connection as sqlx::Connection>::Options;
+
+/// A database the client can write to, providing what differs between the
+/// sqlx drivers.
+///
+/// Implemented for [`sqlx::MySql`] with the `mysql` feature, for
+/// [`sqlx::Postgres`] with the `postgres` feature and for [`sqlx::Sqlite`]
+/// with the `sqlite` feature.
+pub trait Backend: sqlx::Database {
+    #[doc(hidden)]
+    const DIALECT: Dialect;
+
+    fn connect_options(config: &SqlServerSetupConfig) -> ConnectOptions<Self>;
+
+    /// Options of the connection pool.
+    fn pool_options(config: &SqlServerSetupConfig) -> sqlx::pool::PoolOptions<Self> {
+        pool_options(&config.pool)
+    }
+
+    /// Database the options connect to, for logging.
+    fn database_name(options: &ConnectOptions<Self>) -> String;
+
+    #[doc(hidden)]
+    fn push_bind(
+        args: &mut sqlx::query_builder::Separated<'_, '_, Self, &'static str>,
+        value: SqlValue,
+    );
+
+    /// Whether the server rejected a value not fitting its column.
+    #[doc(hidden)]
+    fn is_type_conversion(e: &dyn sqlx::error::DatabaseError) -> bool;
+
+    /// Whether the server rejected the statement for a reason that may not
+    /// occur again, such as a deadlock or a shutdown.
+    #[doc(hidden)]
+    fn is_transient(e: &dyn sqlx::error::DatabaseError) -> bool;
+
+    /// Whether the server rejected adding a column that already exists.
+    #[doc(hidden)]
+    fn is_duplicate_column(e: &dyn sqlx::error::DatabaseError) -> bool;
+
+    /// The name and type of every column of `table`.
+    #[doc(hidden)]
+    fn fetch_columns<'c>(
+        conn: &'c mut Self::Connection,
+        table: &'c str,
+    ) -> BoxFuture<'c, Result<Vec<(String, ColumnType)>, sqlx::Error>>;
+
+    /// Executes the statement, returning the number of affected rows.
+    #[doc(hidden)]
+    fn execute<'c, 'q: 'c>(
+        conn: &'c mut Self::Connection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<u64, sqlx::Error>>;
+
+    /// Runs the query, returning its rows.
+    #[doc(hidden)]
+    fn fetch_all<'c, 'q: 'c>(
+        conn: &'c mut Self::Connection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<Vec<Self::Row>, sqlx::Error>>;
+
+    /// Runs `sql` with `params` bound to its placeholders, returning its
+    /// rows.
+    #[doc(hidden)]
+    fn fetch_with<'c, 'q: 'c>(
+        conn: &'c mut Self::Connection,
+        sql: &'q str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'c, Result<Vec<Self::Row>, sqlx::Error>>;
+
+    /// The row as an object keyed by column name.
+    #[doc(hidden)]
+    fn row_to_json(row: &Self::Row) -> Map<String, Value>;
+}
+
+/// Applies the configured settings of `pool` over the defaults of sqlx.
+pub fn pool_options<B: sqlx::Database>(pool: &sql_server::Pool) -> sqlx::pool::PoolOptions<B> {
+    let ms = std::time::Duration::from_millis;
+    // Zero disables the timeout rather than closing connections at once.
+    let timeout = |ms_opt: u64| (ms_opt > 0).then(|| ms(ms_opt));
+
+    let mut options = sqlx::pool::PoolOptions::new();
+    if let Some(max) = pool.max_connections {
+        options = options.max_connections(max);
+    }
+    if let Some(min) = pool.min_connections {
+        options = options.min_connections(min);
+    }
+    if let Some(t) = pool.acquire_timeout_ms {
+        options = options.acquire_timeout(ms(t));
+    }
+    if let Some(t) = pool.idle_timeout_ms {
+        options = options.idle_timeout(timeout(t));
+    }
+    if let Some(t) = pool.max_lifetime_ms {
+        options = options.max_lifetime(timeout(t));
+    }
+    options
+}
+
+/// Formats a date and time read from the database, as used for JSON.
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+fn format_datetime(t: time::PrimitiveDateTime) -> Value {
+    let format = time::macros::format_description!(
+        "[year]-[month]-[day] [hour]:[minute]:[second][optional [.[subsecond]]]"
+    );
+    t.format(&format).map(Value::from).unwrap_or_default()
+}
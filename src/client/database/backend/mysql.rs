@@ -0,0 +1,217 @@
+use super::{format_datetime, Backend, ConnectOptions};
+use crate::client::database::dialect::Dialect;
+use crate::client::database::schema::ColumnType;
+use crate::client::database::SqlValue;
+use crate::setup_config::sql_server::SslMode;
+use crate::setup_config::SqlServerSetupConfig;
+use futures_util::future::BoxFuture;
+use serde_json::{Map, Value};
+use sqlx::mysql::{MySqlArguments, MySqlConnection, MySqlDatabaseError, MySqlRow, MySqlSslMode};
+use sqlx::{Arguments, Column, MySql, Row, TypeInfo};
+
+impl Backend for MySql {
+    const DIALECT: Dialect = Dialect::MYSQL;
+
+    fn connect_options(config: &SqlServerSetupConfig) -> ConnectOptions<Self> {
+        let mut options = sqlx::mysql::MySqlConnectOptions::new()
+            .host(&config.host)
+            .username(&config.username)
+            .database(&config.database);
+
+        if let Some(password) = &config.password {
+            options = options.password(password);
+        }
+        if let Some(port) = config.port {
+            options = options.port(port);
+        }
+        if let Some(capacity) = config.pool.statement_cache_capacity {
+            options = options.statement_cache_capacity(capacity);
+        }
+        if let Some(ssl) = &config.ssl {
+            options = options.ssl_mode(match ssl.mode {
+                SslMode::Disabled => MySqlSslMode::Disabled,
+                SslMode::Preferred => MySqlSslMode::Preferred,
+                SslMode::Required => MySqlSslMode::Required,
+                SslMode::VerifyCa => MySqlSslMode::VerifyCa,
+                SslMode::VerifyIdentity => MySqlSslMode::VerifyIdentity,
+            });
+            if let Some(ca) = &ssl.ca {
+                options = options.ssl_ca(ca);
+            }
+            if let Some(cert) = &ssl.cert {
+                options = options.ssl_client_cert(cert);
+            }
+            if let Some(key) = &ssl.key {
+                options = options.ssl_client_key(key);
+            }
+        }
+        if let Some(charset) = &config.charset {
+            options = options.charset(charset);
+        }
+        if let Some(collation) = &config.collation {
+            options = options.collation(collation);
+        }
+        if let Some(timezone) = &config.timezone {
+            options = options.timezone(timezone.clone());
+        }
+        options
+    }
+
+    fn database_name(options: &ConnectOptions<Self>) -> String {
+        options.get_database().unwrap_or("{unknown}").to_string()
+    }
+
+    fn push_bind(
+        args: &mut sqlx::query_builder::Separated<'_, '_, Self, &'static str>,
+        value: SqlValue,
+    ) {
+        match value {
+            SqlValue::Null => args.push_bind(None::<String>),
+            SqlValue::Bool(o) => args.push_bind(o),
+            SqlValue::Int(o) => args.push_bind(o),
+            SqlValue::UInt(o) => args.push_bind(o),
+            SqlValue::Float(o) => args.push_bind(o),
+            SqlValue::Decimal(o) | SqlValue::Text(o) => args.push_bind(o),
+            SqlValue::Json(o) => args.push_bind(o.to_string()),
+            SqlValue::DateTime(o) => args.push_bind(o),
+        };
+    }
+
+    fn is_type_conversion(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // ER_WARN_DATA_OUT_OF_RANGE, ER_TRUNCATED_WRONG_VALUE,
+        // ER_TRUNCATED_WRONG_VALUE_FOR_FIELD and ER_DATA_TOO_LONG
+        const CONVERSION_ERRORS: &[u16] = &[1264, 1292, 1366, 1406];
+
+        e.try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|e| CONVERSION_ERRORS.contains(&e.number()))
+    }
+
+    fn is_transient(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // ER_CON_COUNT_ERROR, ER_SERVER_SHUTDOWN, ER_LOCK_WAIT_TIMEOUT,
+        // ER_LOCK_DEADLOCK and ER_CONNECTION_KILLED. A lost connection is
+        // not reported by the server, but as an I/O error.
+        const TRANSIENT_ERRORS: &[u16] = &[1040, 1053, 1205, 1213, 1927];
+
+        e.try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|e| TRANSIENT_ERRORS.contains(&e.number()))
+    }
+
+    fn is_duplicate_column(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // ER_DUP_FIELDNAME
+        e.try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|e| e.number() == 1060)
+    }
+
+    fn fetch_columns<'c>(
+        conn: &'c mut MySqlConnection,
+        table: &'c str,
+    ) -> BoxFuture<'c, Result<Vec<(String, ColumnType)>, sqlx::Error>> {
+        Box::pin(async move {
+            let columns: Vec<(String, String, String)> = sqlx::query_as(
+                "SELECT CAST(COLUMN_NAME AS CHAR), CAST(DATA_TYPE AS CHAR), CAST(COLUMN_TYPE AS CHAR) \
+                 FROM INFORMATION_SCHEMA.COLUMNS \
+                 WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?",
+            )
+            .bind(table)
+            .fetch_all(conn)
+            .await?;
+
+            Ok(columns
+                .into_iter()
+                .map(|(name, data_type, column_type)| {
+                    (
+                        name,
+                        ColumnType::from_information_schema(&data_type, &column_type),
+                    )
+                })
+                .collect())
+        })
+    }
+
+    fn execute<'c, 'q: 'c>(
+        conn: &'c mut MySqlConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<u64, sqlx::Error>> {
+        Box::pin(async move {
+            let result = query.build().execute(conn).await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn fetch_all<'c, 'q: 'c>(
+        conn: &'c mut MySqlConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<Vec<MySqlRow>, sqlx::Error>> {
+        Box::pin(query.build().fetch_all(conn))
+    }
+
+    fn fetch_with<'c, 'q: 'c>(
+        conn: &'c mut MySqlConnection,
+        sql: &'q str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'c, Result<Vec<MySqlRow>, sqlx::Error>> {
+        let mut args = MySqlArguments::default();
+        for value in params {
+            add_argument(&mut args, value);
+        }
+        Box::pin(sqlx::query_with(sql, args).fetch_all(conn))
+    }
+
+    fn row_to_json(row: &MySqlRow) -> Map<String, Value> {
+        row.columns()
+            .iter()
+            .map(|c| {
+                let value = column_to_json(row, c.ordinal(), c.type_info().name());
+                (c.name().to_string(), value.unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+/// Binds a parameter of a query, as [`Backend::push_bind`] binds values of
+/// a write.
+fn add_argument(args: &mut MySqlArguments, value: SqlValue) {
+    match value {
+        SqlValue::Null => args.add(None::<String>),
+        SqlValue::Bool(o) => args.add(o),
+        SqlValue::Int(o) => args.add(o),
+        SqlValue::UInt(o) => args.add(o),
+        SqlValue::Float(o) => args.add(o),
+        SqlValue::Decimal(o) | SqlValue::Text(o) => args.add(o),
+        SqlValue::Json(o) => args.add(o.to_string()),
+        SqlValue::DateTime(o) => args.add(o),
+    }
+}
+
+/// Decodes a column by its type name, `None` for `NULL` or a value that
+/// cannot be represented.
+fn column_to_json(row: &MySqlRow, i: usize, type_name: &str) -> Option<Value> {
+    fn get<'r, T>(row: &'r MySqlRow, i: usize) -> Option<T>
+    where
+        T: sqlx::Decode<'r, MySql> + sqlx::Type<MySql>,
+    {
+        row.try_get::<Option<T>, _>(i).ok().flatten()
+    }
+
+    Some(match type_name {
+        "BOOLEAN" => Value::from(get::<bool>(row, i)?),
+        t if t.ends_with("UNSIGNED") => Value::from(get::<u64>(row, i)?),
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => Value::from(get::<i64>(row, i)?),
+        "YEAR" => Value::from(get::<u16>(row, i)?),
+        "FLOAT" => Value::from(get::<f32>(row, i)? as f64),
+        "DOUBLE" => Value::from(get::<f64>(row, i)?),
+        "DATETIME" | "TIMESTAMP" => format_datetime(get(row, i)?),
+        "DATE" => Value::from(get::<time::Date>(row, i)?.to_string()),
+        "TIME" => Value::from(get::<time::Time>(row, i)?.to_string()),
+        "JSON" => {
+            let text: String = row.try_get_unchecked::<Option<_>, _>(i).ok()??;
+            serde_json::from_str(&text).unwrap_or(Value::String(text))
+        }
+        "NULL" => return None,
+        // Decimals and text of any collation, as sent by the server.
+        _ => {
+            let bytes: Vec<u8> = row.try_get_unchecked::<Option<_>, _>(i).ok()??;
+            Value::from(String::from_utf8_lossy(&bytes).into_owned())
+        }
+    })
+}
@@ -0,0 +1,384 @@
+use super::{format_datetime, Backend, ConnectOptions};
+use crate::client::database::dialect::Dialect;
+use crate::client::database::schema::ColumnType;
+use crate::client::database::SqlValue;
+use crate::setup_config::sql_server::SslMode;
+use crate::setup_config::SqlServerSetupConfig;
+use futures_util::future::BoxFuture;
+use serde_json::{Map, Value};
+use sqlx::postgres::{PgArguments, PgConnection, PgRow, PgSslMode};
+use sqlx::{Arguments, Column, Postgres, Row, TypeInfo, ValueRef};
+
+impl Backend for Postgres {
+    const DIALECT: Dialect = Dialect::POSTGRES;
+
+    fn connect_options(config: &SqlServerSetupConfig) -> ConnectOptions<Self> {
+        let mut options = sqlx::postgres::PgConnectOptions::new()
+            .host(&config.host)
+            .username(&config.username)
+            .database(&config.database);
+
+        if let Some(password) = &config.password {
+            options = options.password(password);
+        }
+        if let Some(port) = config.port {
+            options = options.port(port);
+        }
+        if let Some(capacity) = config.pool.statement_cache_capacity {
+            options = options.statement_cache_capacity(capacity);
+        }
+        if let Some(ssl) = &config.ssl {
+            options = options.ssl_mode(match ssl.mode {
+                SslMode::Disabled => PgSslMode::Disable,
+                SslMode::Preferred => PgSslMode::Prefer,
+                SslMode::Required => PgSslMode::Require,
+                SslMode::VerifyCa => PgSslMode::VerifyCa,
+                SslMode::VerifyIdentity => PgSslMode::VerifyFull,
+            });
+            if let Some(ca) = &ssl.ca {
+                options = options.ssl_root_cert(ca);
+            }
+            if let Some(cert) = &ssl.cert {
+                options = options.ssl_client_cert(cert);
+            }
+            if let Some(key) = &ssl.key {
+                options = options.ssl_client_key(key);
+            }
+        }
+        options
+    }
+
+    /// Sets the time zone of each connection once open, as sqlx starts
+    /// every session in UTC.
+    fn pool_options(config: &SqlServerSetupConfig) -> sqlx::pool::PoolOptions<Self> {
+        let options = super::pool_options(&config.pool);
+        let Some(timezone) = config.timezone.clone() else {
+            return options;
+        };
+        options.after_connect(move |conn, _| {
+            let timezone = timezone.clone();
+            Box::pin(async move {
+                sqlx::query("SELECT set_config('TimeZone', $1, false)")
+                    .bind(timezone)
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            })
+        })
+    }
+
+    fn database_name(options: &ConnectOptions<Self>) -> String {
+        options.get_database().unwrap_or("{unknown}").to_string()
+    }
+
+    /// Values PostgreSQL cannot assign from their bound type are cast, and
+    /// `NULL` is written as such to take the type of its column.
+    fn push_bind(
+        args: &mut sqlx::query_builder::Separated<'_, '_, Self, &'static str>,
+        value: SqlValue,
+    ) {
+        match value {
+            SqlValue::Null => {
+                args.push("NULL");
+            }
+            SqlValue::Bool(o) => {
+                args.push_bind(o);
+            }
+            SqlValue::Int(o) => {
+                args.push_bind(o);
+            }
+            SqlValue::UInt(o) => match i64::try_from(o) {
+                Ok(v) => {
+                    args.push_bind(v);
+                }
+                Err(_) => {
+                    args.push_bind(o.to_string());
+                    args.push_unseparated("::NUMERIC");
+                }
+            },
+            SqlValue::Float(o) => {
+                args.push_bind(o);
+            }
+            SqlValue::Decimal(o) => {
+                args.push_bind(o);
+                args.push_unseparated("::NUMERIC");
+            }
+            SqlValue::Text(o) => {
+                args.push_bind(o);
+            }
+            SqlValue::Json(o) => {
+                args.push_bind(o.to_string());
+                args.push_unseparated("::JSONB");
+            }
+            SqlValue::DateTime(o) => {
+                args.push_bind(o);
+            }
+        }
+    }
+
+    fn is_type_conversion(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // Class 22 data exceptions, and datatype_mismatch for values whose
+        // type cannot be assigned to the column.
+        e.code()
+            .is_some_and(|code| code.starts_with("22") || code == "42804")
+    }
+
+    fn is_transient(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // Class 08 connection exceptions, serialization_failure,
+        // deadlock_detected, too_many_connections, lock_not_available,
+        // and the shutdowns of class 57.
+        e.code().is_some_and(|code| {
+            code.starts_with("08")
+                || [
+                    "40001", "40P01", "53300", "55P03", "57P01", "57P02", "57P03",
+                ]
+                .contains(&code.as_ref())
+        })
+    }
+
+    fn is_duplicate_column(e: &dyn sqlx::error::DatabaseError) -> bool {
+        e.code().is_some_and(|code| code == "42701")
+    }
+
+    fn fetch_columns<'c>(
+        conn: &'c mut PgConnection,
+        table: &'c str,
+    ) -> BoxFuture<'c, Result<Vec<(String, ColumnType)>, sqlx::Error>> {
+        Box::pin(async move {
+            let columns: Vec<(String, String)> = sqlx::query_as(
+                "SELECT column_name::text, data_type::text \
+                 FROM information_schema.columns \
+                 WHERE table_schema = current_schema() AND table_name = $1",
+            )
+            .bind(table)
+            .fetch_all(conn)
+            .await?;
+
+            Ok(columns
+                .into_iter()
+                .map(|(name, data_type)| {
+                    let column_type = ColumnType::from_information_schema(&data_type, &data_type);
+                    (name, column_type)
+                })
+                .collect())
+        })
+    }
+
+    fn execute<'c, 'q: 'c>(
+        conn: &'c mut PgConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<u64, sqlx::Error>> {
+        Box::pin(async move {
+            let result = query.build().execute(conn).await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn fetch_all<'c, 'q: 'c>(
+        conn: &'c mut PgConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<Vec<PgRow>, sqlx::Error>> {
+        Box::pin(query.build().fetch_all(conn))
+    }
+
+    fn fetch_with<'c, 'q: 'c>(
+        conn: &'c mut PgConnection,
+        sql: &'q str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'c, Result<Vec<PgRow>, sqlx::Error>> {
+        let mut args = PgArguments::default();
+        for value in params {
+            add_argument(&mut args, value);
+        }
+        Box::pin(sqlx::query_with(sql, args).fetch_all(conn))
+    }
+
+    fn row_to_json(row: &PgRow) -> Map<String, Value> {
+        row.columns()
+            .iter()
+            .map(|c| {
+                let value = column_to_json(row, c.ordinal(), c.type_info().name());
+                (c.name().to_string(), value.unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+/// Binds a parameter of a query as [`Backend::push_bind`] binds values of
+/// a write, but without casts: decimals, JSON and unsigned values beyond
+/// `BIGINT` are bound as text, which the query casts where needed.
+fn add_argument(args: &mut PgArguments, value: SqlValue) {
+    match value {
+        SqlValue::Null => args.add(None::<String>),
+        SqlValue::Bool(o) => args.add(o),
+        SqlValue::Int(o) => args.add(o),
+        SqlValue::UInt(o) => match i64::try_from(o) {
+            Ok(v) => args.add(v),
+            Err(_) => args.add(o.to_string()),
+        },
+        SqlValue::Float(o) => args.add(o),
+        SqlValue::Decimal(o) | SqlValue::Text(o) => args.add(o),
+        SqlValue::Json(o) => args.add(o.to_string()),
+        SqlValue::DateTime(o) => args.add(o),
+    }
+}
+
+/// Decodes a column by its type name, `None` for `NULL` or a value that
+/// cannot be represented.
+fn column_to_json(row: &PgRow, i: usize, type_name: &str) -> Option<Value> {
+    fn get<'r, T>(row: &'r PgRow, i: usize) -> Option<T>
+    where
+        T: sqlx::Decode<'r, Postgres> + sqlx::Type<Postgres>,
+    {
+        row.try_get::<Option<T>, _>(i).ok().flatten()
+    }
+
+    Some(match type_name {
+        "BOOL" => Value::from(get::<bool>(row, i)?),
+        "INT2" => Value::from(get::<i16>(row, i)?),
+        "INT4" => Value::from(get::<i32>(row, i)?),
+        "INT8" => Value::from(get::<i64>(row, i)?),
+        "FLOAT4" => Value::from(get::<f32>(row, i)? as f64),
+        "FLOAT8" => Value::from(get::<f64>(row, i)?),
+        "NUMERIC" => {
+            let raw = row.try_get_raw(i).ok()?;
+            if raw.is_null() {
+                return None;
+            }
+            Value::from(decode_numeric(raw.as_bytes().ok()?)?)
+        }
+        "TIMESTAMP" => format_datetime(get(row, i)?),
+        "TIMESTAMPTZ" => {
+            let t = get::<time::OffsetDateTime>(row, i)?.to_offset(time::UtcOffset::UTC);
+            format_datetime(time::PrimitiveDateTime::new(t.date(), t.time()))
+        }
+        "DATE" => Value::from(get::<time::Date>(row, i)?.to_string()),
+        "TIME" => Value::from(get::<time::Time>(row, i)?.to_string()),
+        "JSON" | "JSONB" => {
+            // Binary JSONB is prefixed with its format version.
+            let raw = row.try_get_raw(i).ok()?;
+            if raw.is_null() {
+                return None;
+            }
+            let bytes = raw.as_bytes().ok()?;
+            let bytes = match type_name {
+                "JSONB" => bytes.get(1..)?,
+                _ => bytes,
+            };
+            serde_json::from_slice(bytes).ok()?
+        }
+        _ => Value::from(get::<String>(row, i)?),
+    })
+}
+
+/// Formats a `NUMERIC` in the binary format: digit count, weight, sign and
+/// display scale, followed by base 10000 digits.
+fn decode_numeric(bytes: &[u8]) -> Option<String> {
+    let word = |i: usize| -> Option<i32> {
+        let b = bytes.get(i * 2..i * 2 + 2)?;
+        Some(u16::from_be_bytes([b[0], b[1]]) as i32)
+    };
+
+    let ndigits = word(0)?;
+    let weight = word(1)? as i16 as i32;
+    let sign = word(2)?;
+    let dscale = word(3)? as usize;
+    let digit = |k: i32| -> Option<i32> {
+        if (0..ndigits).contains(&k) {
+            word(4 + k as usize)
+        } else {
+            Some(0)
+        }
+    };
+
+    match sign {
+        0x0000 | 0x4000 => {}
+        0xC000 => return Some("NaN".to_string()),
+        _ => return None,
+    }
+
+    let mut out = String::new();
+    if sign == 0x4000 {
+        out.push('-');
+    }
+
+    if weight < 0 {
+        out.push('0');
+    } else {
+        for k in 0..=weight {
+            if k == 0 {
+                out.push_str(&digit(k)?.to_string());
+            } else {
+                out.push_str(&format!("{:04}", digit(k)?));
+            }
+        }
+    }
+
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut k = weight + 1;
+        while fraction.len() < dscale {
+            fraction.push_str(&format!("{:04}", digit(k)?));
+            k += 1;
+        }
+        fraction.truncate(dscale);
+        out.push('.');
+        out.push_str(&fraction);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn numeric(weight: i16, sign: u16, dscale: u16, digits: &[u16]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for word in [digits.len() as u16, weight as u16, sign, dscale]
+            .iter()
+            .chain(digits)
+        {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn decodes_binary_numerics() {
+        assert_eq!(
+            decode_numeric(&numeric(1, 0, 2, &[12, 3456, 7800])).unwrap(),
+            "123456.78"
+        );
+        assert_eq!(
+            decode_numeric(&numeric(-2, 0x4000, 8, &[12])).unwrap(),
+            "-0.00000012"
+        );
+        assert_eq!(decode_numeric(&numeric(0, 0, 0, &[])).unwrap(), "0");
+        assert_eq!(
+            decode_numeric(&numeric(2, 0, 0, &[1])).unwrap(),
+            "100000000"
+        );
+    }
+
+    #[test]
+    fn applies_connection_settings() {
+        let config: SqlServerSetupConfig = serde_json::from_value(serde_json::json!({
+            "host": "db",
+            "username": "forwarder",
+            "database": "telemetry",
+            "topic_table_map": {},
+            "pool": {"max_connections": 4, "acquire_timeout_ms": 500, "idle_timeout_ms": 0},
+            "ssl": {"mode": "verify_identity", "ca": "ca.pem"},
+            "timezone": "UTC",
+        }))
+        .unwrap();
+
+        let options = Postgres::connect_options(&config);
+        assert!(matches!(options.get_ssl_mode(), PgSslMode::VerifyFull));
+
+        let pool = Postgres::pool_options(&config);
+        assert_eq!(pool.get_max_connections(), 4);
+        assert_eq!(pool.get_acquire_timeout().as_millis(), 500);
+        assert_eq!(pool.get_idle_timeout(), None);
+    }
+}
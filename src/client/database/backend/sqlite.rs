@@ -0,0 +1,266 @@
+use super::{Backend, ConnectOptions};
+use crate::client::database::dialect::Dialect;
+use crate::client::database::schema::ColumnType;
+use crate::client::database::SqlValue;
+use crate::setup_config::SqlServerSetupConfig;
+use futures_util::future::BoxFuture;
+use serde_json::{Map, Value};
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqliteConnection, SqliteRow};
+use sqlx::{Arguments, Column, Row, Sqlite, TypeInfo, ValueRef};
+use std::str::FromStr;
+
+/// Database path of a private database in memory.
+const IN_MEMORY: &str = ":memory:";
+
+impl Backend for Sqlite {
+    const DIALECT: Dialect = Dialect::SQLITE;
+
+    /// Opens the file at `database`, creating it if missing. Of the
+    /// connection settings, only `database` and the statement cache
+    /// capacity are used.
+    fn connect_options(config: &SqlServerSetupConfig) -> ConnectOptions<Self> {
+        let options = if config.database == IN_MEMORY {
+            // Shared between the connections of a pool, unlike a plain
+            // `:memory:` file name.
+            SqliteConnectOptions::from_str("sqlite::memory:")
+                .expect("in memory database url is valid")
+        } else {
+            SqliteConnectOptions::new()
+                .filename(&config.database)
+                .create_if_missing(true)
+        };
+
+        match config.pool.statement_cache_capacity {
+            Some(capacity) => options.statement_cache_capacity(capacity),
+            None => options,
+        }
+    }
+
+    /// A single connection whatever `max_connections`: SQLite has one
+    /// writer at a time, and sqlx may run a statement that failed on one
+    /// connection again once another has changed the schema. A database in
+    /// memory lives as long as its connection, which is then never closed.
+    fn pool_options(config: &SqlServerSetupConfig) -> sqlx::pool::PoolOptions<Self> {
+        let pool = &config.pool;
+        if pool.max_connections.is_some_and(|max| max > 1) {
+            log::warn!("Ignoring max_connections, SQLite uses a single connection");
+        }
+        let options = super::pool_options(pool).max_connections(1);
+        if config.database == IN_MEMORY {
+            options
+                .min_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+        } else {
+            options.min_connections(pool.min_connections.unwrap_or_default().min(1))
+        }
+    }
+
+    fn database_name(options: &ConnectOptions<Self>) -> String {
+        options.clone().get_filename().display().to_string()
+    }
+
+    /// Unsigned values beyond the range of `INTEGER` are written as text.
+    fn push_bind(
+        args: &mut sqlx::query_builder::Separated<'_, '_, Self, &'static str>,
+        value: SqlValue,
+    ) {
+        match value {
+            SqlValue::Null => args.push_bind(None::<String>),
+            SqlValue::Bool(o) => args.push_bind(o),
+            SqlValue::Int(o) => args.push_bind(o),
+            SqlValue::UInt(o) => match i64::try_from(o) {
+                Ok(v) => args.push_bind(v),
+                Err(_) => args.push_bind(o.to_string()),
+            },
+            SqlValue::Float(o) => args.push_bind(o),
+            SqlValue::Decimal(o) | SqlValue::Text(o) => args.push_bind(o),
+            SqlValue::Json(o) => args.push_bind(o.to_string()),
+            SqlValue::DateTime(o) => args.push_bind(o),
+        };
+    }
+
+    fn is_type_conversion(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // SQLITE_MISMATCH, and SQLITE_CONSTRAINT_DATATYPE of STRICT tables.
+        e.code().is_some_and(|code| code == "20" || code == "3091")
+    }
+
+    fn is_transient(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // SQLITE_BUSY and SQLITE_LOCKED, with any extended code.
+        e.code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6))
+    }
+
+    fn is_duplicate_column(e: &dyn sqlx::error::DatabaseError) -> bool {
+        // Reported as a generic SQLITE_ERROR.
+        e.message().starts_with("duplicate column name")
+    }
+
+    fn fetch_columns<'c>(
+        conn: &'c mut SqliteConnection,
+        table: &'c str,
+    ) -> BoxFuture<'c, Result<Vec<(String, ColumnType)>, sqlx::Error>> {
+        Box::pin(async move {
+            let columns: Vec<(String, String)> =
+                sqlx::query_as("SELECT name, type FROM pragma_table_info(?)")
+                    .bind(table)
+                    .fetch_all(conn)
+                    .await?;
+
+            Ok(columns
+                .into_iter()
+                .map(|(name, declared)| (name, column_type(&declared)))
+                .collect())
+        })
+    }
+
+    fn execute<'c, 'q: 'c>(
+        conn: &'c mut SqliteConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<u64, sqlx::Error>> {
+        Box::pin(async move {
+            let result = query.build().execute(conn).await?;
+            Ok(result.rows_affected())
+        })
+    }
+
+    fn fetch_all<'c, 'q: 'c>(
+        conn: &'c mut SqliteConnection,
+        query: &'c mut sqlx::QueryBuilder<'q, Self>,
+    ) -> BoxFuture<'c, Result<Vec<SqliteRow>, sqlx::Error>> {
+        Box::pin(query.build().fetch_all(conn))
+    }
+
+    fn fetch_with<'c, 'q: 'c>(
+        conn: &'c mut SqliteConnection,
+        sql: &'q str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'c, Result<Vec<SqliteRow>, sqlx::Error>> {
+        let mut args = SqliteArguments::default();
+        for value in params {
+            add_argument(&mut args, value);
+        }
+        Box::pin(sqlx::query_with(sql, args).fetch_all(conn))
+    }
+
+    fn row_to_json(row: &SqliteRow) -> Map<String, Value> {
+        row.columns()
+            .iter()
+            .map(|c| {
+                let value = column_to_json(row, c.ordinal(), c.type_info().name());
+                (c.name().to_string(), value.unwrap_or_default())
+            })
+            .collect()
+    }
+}
+
+/// Binds a parameter of a query, as [`Backend::push_bind`] binds values of
+/// a write.
+fn add_argument(args: &mut SqliteArguments<'_>, value: SqlValue) {
+    match value {
+        SqlValue::Null => args.add(None::<String>),
+        SqlValue::Bool(o) => args.add(o),
+        SqlValue::Int(o) => args.add(o),
+        SqlValue::UInt(o) => match i64::try_from(o) {
+            Ok(v) => args.add(v),
+            Err(_) => args.add(o.to_string()),
+        },
+        SqlValue::Float(o) => args.add(o),
+        SqlValue::Decimal(o) | SqlValue::Text(o) => args.add(o),
+        SqlValue::Json(o) => args.add(o.to_string()),
+        SqlValue::DateTime(o) => args.add(o),
+    }
+}
+
+/// Maps a declared column type, which SQLite does not restrict, following
+/// the rules of its type affinity.
+fn column_type(declared: &str) -> ColumnType {
+    let t = declared.to_ascii_uppercase();
+    if t.contains("BOOL") {
+        ColumnType::Boolean
+    } else if t.contains("INT") {
+        ColumnType::Integer {
+            unsigned: t.contains("UNSIGNED"),
+        }
+    } else if t.contains("JSON") {
+        ColumnType::Json
+    } else if t.contains("CHAR") || t.contains("CLOB") || t.contains("TEXT") {
+        ColumnType::Text
+    } else if t.contains("REAL") || t.contains("FLOA") || t.contains("DOUB") {
+        ColumnType::Float
+    } else if t.contains("DATE") || t.contains("TIME") {
+        ColumnType::DateTime
+    } else if t.contains("DEC") || t.contains("NUM") {
+        ColumnType::Decimal
+    } else {
+        ColumnType::Other(declared.to_ascii_lowercase())
+    }
+}
+
+/// Decodes a column by the storage class of its value, `None` for `NULL` or
+/// a value that cannot be represented. Integers of a `BOOLEAN` column are
+/// read as booleans, dates and times are kept as the stored text.
+fn column_to_json(row: &SqliteRow, i: usize, type_name: &str) -> Option<Value> {
+    let raw = row.try_get_raw(i).ok()?;
+    if raw.is_null() {
+        return None;
+    }
+    let storage = raw.type_info().name().to_string();
+
+    Some(match (storage.as_str(), type_name) {
+        ("INTEGER", "BOOLEAN") => Value::from(row.try_get::<bool, _>(i).ok()?),
+        ("INTEGER", _) => Value::from(row.try_get::<i64, _>(i).ok()?),
+        ("REAL", _) => Value::from(row.try_get::<f64, _>(i).ok()?),
+        ("BLOB", _) => {
+            let bytes: Vec<u8> = row.try_get(i).ok()?;
+            Value::from(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => Value::from(row.try_get_unchecked::<String, _>(i).ok()?),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_declared_types() {
+        assert_eq!(column_type("BOOLEAN"), ColumnType::Boolean);
+        assert_eq!(
+            column_type("UNSIGNED BIG INT"),
+            ColumnType::Integer { unsigned: true }
+        );
+        assert_eq!(
+            column_type("integer"),
+            ColumnType::Integer { unsigned: false }
+        );
+        assert_eq!(column_type("VARCHAR(32)"), ColumnType::Text);
+        assert_eq!(column_type("JSON"), ColumnType::Json);
+        assert_eq!(column_type("DOUBLE"), ColumnType::Float);
+        assert_eq!(column_type("DATETIME"), ColumnType::DateTime);
+        assert_eq!(column_type("DECIMAL(10, 2)"), ColumnType::Decimal);
+        assert_eq!(column_type("BLOB"), ColumnType::Other("blob".into()));
+    }
+
+    #[test]
+    fn keeps_databases_in_memory_open() {
+        let config = |database: &str| {
+            serde_json::from_value::<SqlServerSetupConfig>(serde_json::json!({
+                "database": database,
+                "pool": {"idle_timeout_ms": 1000, "max_lifetime_ms": 1000},
+            }))
+            .unwrap()
+        };
+
+        let options = Sqlite::pool_options(&config(IN_MEMORY));
+        assert_eq!(options.get_min_connections(), 1);
+        assert_eq!(options.get_idle_timeout(), None);
+        assert_eq!(options.get_max_lifetime(), None);
+
+        let options = Sqlite::pool_options(&config("telemetry.db"));
+        assert_eq!(options.get_min_connections(), 0);
+        assert!(options.get_idle_timeout().is_some());
+        assert!(options.get_max_lifetime().is_some());
+    }
+}
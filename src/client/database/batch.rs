@@ -0,0 +1,504 @@
+use super::dead_letter::DeadLetter;
+use super::setup_config::sql_server::WriteMode;
+use super::{Backend, DbClientError, MessageInfo, Row, SqlClient, SqlValue};
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+pub struct BatchConfig {
+    /// Rows of a single batch after which it is flushed.
+    pub max_rows: usize,
+    /// Time after which a batch is flushed, counted from its first row.
+    pub max_delay: Duration,
+    /// Rows held across all batches after which everything is flushed,
+    /// bounding the memory used by the writer.
+    pub max_pending_rows: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: 500,
+            max_delay: Duration::from_secs(1),
+            max_pending_rows: 10_000,
+        }
+    }
+}
+
+/// A row that could not be inserted, even on its own, nor buffered, whose
+/// message was dead lettered.
+#[derive(Debug)]
+pub struct FailedRow {
+    pub topic: String,
+    pub row: Row,
+    pub error: DbClientError,
+}
+
+/// Outcome of flushing one batch.
+#[derive(Debug)]
+pub struct BatchReport {
+    pub table: String,
+    pub inserted: usize,
+    /// Rows whose messages were buffered by the client, the database being
+    /// unavailable, to be written with its buffer.
+    pub buffered: usize,
+    /// The error of the multi-row insert, after which the rows were
+    /// buffered if transient, or inserted one by one otherwise.
+    pub batch_error: Option<DbClientError>,
+    pub failed: Vec<FailedRow>,
+}
+
+impl BatchReport {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct BatchKey {
+    table: String,
+    mode: WriteMode,
+    columns: Vec<String>,
+}
+
+struct Batch {
+    started: Instant,
+    max_rows: usize,
+    /// The message of each row, buffered or dead lettered if it fails, with
+    /// its number among the pushed messages.
+    messages: Vec<(u64, DeadLetter)>,
+    rows: Vec<Vec<SqlValue>>,
+}
+
+/// A message mapped to several tables, whose rows are batched apart.
+struct FanOut {
+    /// Rows not yet written, buffered or dead lettered.
+    rows_left: usize,
+    /// Whether the message was buffered, or else dead lettered, once one of
+    /// its rows failed.
+    deferred: Option<bool>,
+}
+
+/// Groups the pushed messages per target table, write mode and column set,
+/// and writes each group with multi-row statements.
+///
+/// The rows of a topic mapped to several tables are batched per table, so
+/// unlike [`SqlClient::push`] they are not written within one transaction.
+///
+/// Batches are written as by [`SqlClient::push_with_info`]: transient
+/// failures are retried, then the messages are buffered by the client,
+/// while the rows failing otherwise are dead lettered. While the client
+/// buffers messages, batches are buffered behind them to keep their order.
+/// A message mapped to several tables is buffered or dead lettered whole,
+/// once: its rows still batched are then left to the buffered message,
+/// while those already inserted are written again with it.
+///
+/// Nothing is flushed in the background, the owner of the writer drives it:
+/// it must call [`BatchWriter::flush_due`] once [`BatchWriter::next_deadline`]
+/// has passed, or batches wait for the next full one, and
+/// [`BatchWriter::flush`] before dropping the writer, or the pending rows
+/// are lost. For instance, with the messages of an
+/// [`MqttReceiver`](crate::MqttReceiver):
+///
+/// ```no_run
+/// # use saltyfishie_clients::{Backend, BatchWriter, MqttReceiver};
+/// async fn forward<B: Backend>(writer: &mut BatchWriter<'_, B>, receiver: &mut MqttReceiver) {
+///     let client_id = receiver.client().client_id();
+///     loop {
+///         let deadline = writer.next_deadline();
+///         tokio::select! {
+///             Some(msg) = receiver.poll() => {
+///                 if let Err(e) = writer.push_message(&msg, &client_id).await {
+///                     log::error!("{}", e);
+///                 }
+///             }
+///             _ = tokio::time::sleep_until(deadline.unwrap()), if deadline.is_some() => {
+///                 writer.flush_due().await;
+///             }
+///         }
+///     }
+/// }
+/// ```
+pub struct BatchWriter<'a, B: Backend> {
+    client: &'a SqlClient<B>,
+    config: BatchConfig,
+    batches: HashMap<BatchKey, Batch>,
+    pending_rows: usize,
+    /// Messages with rows in several batches, by number.
+    fan_outs: HashMap<u64, FanOut>,
+    next_message: u64,
+}
+
+impl<'a, B: Backend> BatchWriter<'a, B> {
+    pub fn new(client: &'a SqlClient<B>, config: BatchConfig) -> Self {
+        Self {
+            client,
+            config,
+            batches: HashMap::new(),
+            pending_rows: 0,
+            fan_outs: HashMap::new(),
+            next_message: 0,
+        }
+    }
+
+    /// Queues the message for insertion.
+    ///
+    /// Fails if the message cannot be converted to a row, otherwise returns
+    /// the reports of the batches flushed to make room for it.
+    pub async fn push(
+        &mut self,
+        topic: &str,
+        payload: &str,
+    ) -> Result<Vec<BatchReport>, DbClientError> {
+        self.push_with_info(&MessageInfo::new(topic), payload.as_bytes())
+            .await
+    }
+
+    /// Queues a message received by the client `client_id`.
+    pub async fn push_message(
+        &mut self,
+        msg: &paho_mqtt::Message,
+        client_id: &str,
+    ) -> Result<Vec<BatchReport>, DbClientError> {
+        let info = MessageInfo::from(msg).with_client_id(client_id);
+        self.push_with_info(&info, msg.payload()).await
+    }
+
+    pub async fn push_with_info(
+        &mut self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+    ) -> Result<Vec<BatchReport>, DbClientError> {
+        let rows = self.client.to_rows(info, payload).await?;
+        let message = self.next_message;
+        self.next_message += 1;
+        if rows.len() > 1 {
+            let fan_out = FanOut {
+                rows_left: rows.len(),
+                deferred: None,
+            };
+            self.fan_outs.insert(message, fan_out);
+        }
+
+        for (mapping, row) in rows {
+            let key = BatchKey {
+                table: mapping.table.clone(),
+                mode: mapping.write.clone(),
+                columns: row.columns,
+            };
+
+            let max_rows = self
+                .config
+                .max_rows
+                .min(B::DIALECT.max_placeholders / key.columns.len().max(1))
+                .max(1);
+
+            let batch = self.batches.entry(key).or_insert_with(|| Batch {
+                started: Instant::now(),
+                max_rows,
+                messages: Vec::new(),
+                rows: Vec::new(),
+            });
+            let letter = DeadLetter::new(info, payload, "not yet written");
+            batch.messages.push((message, letter));
+            batch.rows.push(row.values);
+            self.pending_rows += 1;
+        }
+
+        let mut reports = Vec::new();
+        if self.pending_rows >= self.config.max_pending_rows {
+            reports = self.flush().await;
+        } else {
+            let full: Vec<_> = self.take_batches(|b| b.rows.len() >= b.max_rows);
+            for (key, batch) in full {
+                reports.push(self.write(key, batch).await);
+            }
+        }
+        Ok(reports)
+    }
+
+    /// When the oldest pending batch is due, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.batches
+            .values()
+            .map(|b| b.started + self.config.max_delay)
+            .min()
+    }
+
+    /// Flushes the batches older than the configured delay.
+    pub async fn flush_due(&mut self) -> Vec<BatchReport> {
+        let now = Instant::now();
+        let max_delay = self.config.max_delay;
+        let due = self.take_batches(|b| b.started + max_delay <= now);
+
+        let mut reports = Vec::with_capacity(due.len());
+        for (key, batch) in due {
+            reports.push(self.write(key, batch).await);
+        }
+        reports
+    }
+
+    /// Flushes every pending batch.
+    pub async fn flush(&mut self) -> Vec<BatchReport> {
+        let all = self.take_batches(|_| true);
+
+        let mut reports = Vec::with_capacity(all.len());
+        for (key, batch) in all {
+            reports.push(self.write(key, batch).await);
+        }
+        reports
+    }
+
+    pub fn pending_rows(&self) -> usize {
+        self.pending_rows
+    }
+
+    fn take_batches(&mut self, pred: impl Fn(&Batch) -> bool) -> Vec<(BatchKey, Batch)> {
+        let keys: Vec<_> = self
+            .batches
+            .iter()
+            .filter(|(_, b)| pred(b))
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let mut out = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(batch) = self.batches.remove(&key) {
+                self.pending_rows -= batch.rows.len();
+                out.push((key, batch));
+            }
+        }
+        out
+    }
+
+    /// Inserts the batch at once. If that fails, its messages are buffered
+    /// if the database is unavailable, otherwise the rows are inserted one
+    /// by one to isolate those at fault.
+    async fn write(&mut self, key: BatchKey, mut batch: Batch) -> BatchReport {
+        let mut report = BatchReport {
+            table: key.table.clone(),
+            inserted: 0,
+            buffered: 0,
+            batch_error: None,
+            failed: Vec::new(),
+        };
+
+        // Rows of messages already buffered are written with them.
+        let messages = std::mem::take(&mut batch.messages);
+        for ((message, letter), values) in messages.into_iter().zip(std::mem::take(&mut batch.rows))
+        {
+            if self.deferred(message) == Some(true) {
+                self.settle(message);
+                report.buffered += 1;
+            } else {
+                batch.messages.push((message, letter));
+                batch.rows.push(values);
+            }
+        }
+        if batch.rows.is_empty() {
+            return report;
+        }
+
+        // Queued behind the buffered messages to keep their order.
+        let queued = self.client.is_buffering().await;
+        let batch_error = match queued {
+            true => None,
+            false => match self
+                .client
+                .insert_with_retry(None, &key.table, &key.mode, &key.columns, &batch.rows)
+                .await
+            {
+                Ok(_) => {
+                    report.inserted = batch.rows.len();
+                    for (message, _) in &batch.messages {
+                        self.settle(*message);
+                    }
+                    return report;
+                }
+                Err(e) => Some(e),
+            },
+        };
+
+        let transient = batch_error
+            .as_ref()
+            .is_some_and(DbClientError::is_transient);
+        if let Some(batch_error) = &batch_error {
+            log::warn!(
+                "Batch insert of {} rows into '{}' failed, {}: {}",
+                batch.rows.len(),
+                key.table,
+                if transient {
+                    "buffering them"
+                } else {
+                    "retrying row by row"
+                },
+                batch_error
+            );
+        }
+
+        for ((message, letter), values) in batch.messages.into_iter().zip(batch.rows) {
+            // Not worth attempting row by row while unavailable.
+            let error = match queued || transient {
+                true => None,
+                false => match self
+                    .client
+                    .insert_with_retry(
+                        Some(&letter.topic),
+                        &key.table,
+                        &key.mode,
+                        &key.columns,
+                        std::slice::from_ref(&values),
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        self.settle(message);
+                        report.inserted += 1;
+                        continue;
+                    }
+                    Err(error) => Some(error),
+                },
+            };
+
+            let buffered = self
+                .defer(message, &letter, error.as_ref().or(batch_error.as_ref()))
+                .await;
+            self.settle(message);
+            if buffered {
+                report.buffered += 1;
+                continue;
+            }
+            report.failed.push(FailedRow {
+                topic: letter.topic,
+                row: Row {
+                    columns: key.columns.clone(),
+                    values,
+                },
+                error: error.unwrap_or(DbClientError::BufferFull),
+            });
+        }
+
+        report.batch_error = batch_error;
+        report
+    }
+
+    /// Buffers or dead letters the message of a failed row, unless done for
+    /// another of its rows, returning whether it is buffered. Without an
+    /// error, it is queued behind the buffered messages.
+    async fn defer(
+        &mut self,
+        message: u64,
+        letter: &DeadLetter,
+        error: Option<&DbClientError>,
+    ) -> bool {
+        if let Some(buffered) = self.deferred(message) {
+            return buffered;
+        }
+        let info = letter.info();
+        let buffered = match error {
+            Some(error) => self.client.defer(&info, &letter.payload, error).await,
+            None => self.client.queue(&info, &letter.payload).await,
+        };
+        if let Some(fan_out) = self.fan_outs.get_mut(&message) {
+            fan_out.deferred = Some(buffered);
+        }
+        buffered
+    }
+
+    /// Whether the message was buffered, or else dead lettered, if so.
+    fn deferred(&self, message: u64) -> Option<bool> {
+        self.fan_outs.get(&message).and_then(|f| f.deferred)
+    }
+
+    /// Forgets a message once all its rows are done with.
+    fn settle(&mut self, message: u64) {
+        if let Some(fan_out) = self.fan_outs.get_mut(&message) {
+            fan_out.rows_left -= 1;
+            if fan_out.rows_left == 0 {
+                self.fan_outs.remove(&message);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mysql"))]
+mod test {
+    use super::*;
+    use crate::setup_config::sql_server::{NestedValues, UnknownColumns};
+    use crate::{MysqlBatchWriter, MysqlClient, MysqlClientConfig};
+
+    fn lazy_client() -> MysqlClient {
+        MysqlClient::start_lazy(MysqlClientConfig {
+            connect_options: sqlx::mysql::MySqlConnectOptions::new(),
+            pool_options: Default::default(),
+            topic_table_map: [
+                ("a".to_string(), "table_a".into()),
+                ("b".to_string(), "table_b".into()),
+                (
+                    "fan".to_string(),
+                    serde_json::from_value(serde_json::json!([
+                        "archive",
+                        {"table": "latest", "fields": {"x": "x"}},
+                    ]))
+                    .unwrap(),
+                ),
+            ]
+            .into(),
+            nested_values: NestedValues::Reject,
+            unknown_columns: UnknownColumns::Allow,
+            coerce_types: false,
+            schema_evolution: Default::default(),
+            dead_letter: None,
+            retry: Default::default(),
+            buffer: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn groups_rows_by_table_and_columns() {
+        let client = lazy_client();
+        let mut writer = MysqlBatchWriter::new(&client, BatchConfig::default());
+
+        for (topic, payload) in [
+            ("a", r#"{"x": 1}"#),
+            ("a", r#"{"x": 2}"#),
+            ("a", r#"{"x": 3, "y": 1}"#),
+            ("b", r#"{"x": 1}"#),
+        ] {
+            assert!(writer.push(topic, payload).await.unwrap().is_empty());
+        }
+
+        assert_eq!(writer.pending_rows(), 4);
+        assert_eq!(writer.batches.len(), 3);
+        assert!(writer.next_deadline().is_some());
+    }
+
+    #[tokio::test]
+    async fn batches_fan_out_rows_per_table() {
+        let client = lazy_client();
+        let mut writer = MysqlBatchWriter::new(&client, BatchConfig::default());
+
+        writer.push("fan", r#"{"x": 1, "y": 2}"#).await.unwrap();
+
+        assert_eq!(writer.pending_rows(), 2);
+        let mut tables: Vec<_> = writer
+            .batches
+            .keys()
+            .map(|k| (k.table.as_str(), k.columns.len()))
+            .collect();
+        tables.sort();
+        assert_eq!(tables, [("archive", 2), ("latest", 1)]);
+    }
+
+    #[tokio::test]
+    async fn rejects_messages_before_batching() {
+        let client = lazy_client();
+        let mut writer = MysqlBatchWriter::new(&client, BatchConfig::default());
+
+        assert!(matches!(
+            writer.push("missing", r#"{"x": 1}"#).await,
+            Err(DbClientError::UnknownMapping { .. })
+        ));
+        assert_eq!(writer.pending_rows(), 0);
+        assert!(writer.next_deadline().is_none());
+    }
+}
@@ -0,0 +1,1340 @@
+use super::backend::{Backend, ConnectOptions};
+use super::dead_letter::{self, DeadLetter, ReplayReport};
+use super::query::{self, TableQuery};
+use super::write::StatementError;
+use super::{coerce, evolve, identifier, mapping, retry, schema, write};
+use super::{DbClientError, MessageInfo, Row, SqlValue, TableSchema};
+use crate::setup_config;
+use crate::setup_config::sql_server::{DeadLetterStore, WriteMode};
+use crate::MqttClient;
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct SqlClientConfig<B: Backend> {
+    pub connect_options: ConnectOptions<B>,
+    pub pool_options: sqlx::pool::PoolOptions<B>,
+    pub topic_table_map: setup_config::sql_server::TopicTableMapping,
+    pub nested_values: setup_config::sql_server::NestedValues,
+    pub unknown_columns: setup_config::sql_server::UnknownColumns,
+    pub coerce_types: bool,
+    pub schema_evolution: setup_config::sql_server::SchemaEvolution,
+    pub dead_letter: Option<setup_config::sql_server::DeadLetterStore>,
+    pub retry: setup_config::sql_server::Retry,
+    pub buffer: setup_config::sql_server::Buffer,
+}
+
+impl<B: Backend> From<setup_config::SqlServerSetupConfig> for SqlClientConfig<B> {
+    fn from(config: setup_config::SqlServerSetupConfig) -> Self {
+        Self {
+            connect_options: B::connect_options(&config),
+            pool_options: B::pool_options(&config),
+            topic_table_map: config.topic_table_map,
+            nested_values: config.nested_values,
+            unknown_columns: config.unknown_columns,
+            coerce_types: config.coerce_types,
+            schema_evolution: config.schema_evolution,
+            dead_letter: config.dead_letter,
+            retry: config.retry,
+            buffer: config.buffer,
+        }
+    }
+}
+
+/// Writes MQTT payloads to the tables mapped to their topics, in the
+/// database of the backend `B`.
+pub struct SqlClient<B: Backend> {
+    connection_pool: sqlx::Pool<B>,
+    topic_table_map: setup_config::sql_server::TopicTableMapping,
+    nested_values: setup_config::sql_server::NestedValues,
+    unknown_columns: setup_config::sql_server::UnknownColumns,
+    coerce_types: bool,
+    schema_evolution: setup_config::sql_server::SchemaEvolution,
+    schema: schema::SchemaCache,
+    /// Statements already logged in dry run mode, so each is logged once.
+    dry_run_logged: std::sync::Mutex<std::collections::HashSet<String>>,
+    dead_letter: Option<setup_config::sql_server::DeadLetterStore>,
+    dead_letter_publisher: std::sync::OnceLock<MqttClient>,
+    /// Set once the dead letter table is known to exist.
+    dead_letter_table: tokio::sync::OnceCell<()>,
+    /// Held while the dead letter file is written.
+    dead_letter_file: tokio::sync::Mutex<()>,
+    retry: setup_config::sql_server::Retry,
+    /// Messages waiting for the database to be available again.
+    buffer: tokio::sync::Mutex<retry::Buffer>,
+}
+
+impl<B: Backend> SqlClient<B> {
+    pub async fn start(config: SqlClientConfig<B>) -> Result<Self, DbClientError> {
+        let connection_pool = config
+            .pool_options
+            .clone()
+            .connect_with(config.connect_options.clone())
+            .await
+            .map_err(DbClientError::Connection)?;
+
+        log::info!(
+            "Connected to {} database '{}'!",
+            <B as sqlx::Database>::NAME,
+            B::database_name(&connection_pool.connect_options())
+        );
+
+        let out = Self::with_pool(connection_pool, config);
+        if out.uses_schema() {
+            out.refresh_schema().await?;
+        }
+        Ok(out)
+    }
+
+    /// Builds a client whose pool only connects on first use.
+    #[cfg(all(test, feature = "mysql"))]
+    pub(crate) fn start_lazy(config: SqlClientConfig<B>) -> Self {
+        let pool = config
+            .pool_options
+            .clone()
+            .connect_lazy_with(config.connect_options.clone());
+        Self::with_pool(pool, config)
+    }
+
+    fn with_pool(connection_pool: sqlx::Pool<B>, config: SqlClientConfig<B>) -> Self {
+        Self {
+            connection_pool,
+            topic_table_map: config.topic_table_map,
+            nested_values: config.nested_values,
+            unknown_columns: config.unknown_columns,
+            coerce_types: config.coerce_types,
+            schema_evolution: config.schema_evolution,
+            schema: Default::default(),
+            dry_run_logged: Default::default(),
+            dead_letter: config.dead_letter,
+            dead_letter_publisher: Default::default(),
+            dead_letter_table: Default::default(),
+            dead_letter_file: Default::default(),
+            retry: config.retry,
+            buffer: tokio::sync::Mutex::new(retry::Buffer::new(config.buffer)),
+        }
+    }
+
+    fn uses_schema(&self) -> bool {
+        self.coerce_types
+            || self.unknown_columns != setup_config::sql_server::UnknownColumns::Allow
+            || self.schema_evolution.is_enabled()
+    }
+
+    /// Reads the schemas of the mapped tables again, e.g. after they were
+    /// altered.
+    pub async fn refresh_schema(&self) -> Result<(), DbClientError> {
+        let tables: Vec<&str> = self
+            .topic_table_map
+            .values()
+            .flat_map(|m| &m.tables)
+            .map(|t| t.table.as_str())
+            .collect();
+
+        self.schema
+            .refresh(&self.connection_pool, tables)
+            .await
+            .map_err(|(table, e)| DbClientError::from_query::<B>(None, &table, e))
+    }
+
+    /// The cached schema of `table`.
+    pub async fn table_schema(&self, table: &str) -> Result<Arc<TableSchema>, DbClientError> {
+        self.schema
+            .table(&self.connection_pool, table)
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(None, table, e))
+    }
+
+    /// Runs `sql` with `params` bound to its placeholders, `?` for MySQL
+    /// and SQLite or `$1`, `$2`... for PostgreSQL, returning each row as an
+    /// object keyed by column name.
+    pub async fn query(
+        &self,
+        sql: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<serde_json::Value>, DbClientError> {
+        let rows = self.fetch_with(sql, params).await?;
+        Ok(rows
+            .iter()
+            .map(|row| serde_json::Value::Object(B::row_to_json(row)))
+            .collect())
+    }
+
+    /// Runs `sql` like [`Self::query`], decoding each row into `T`.
+    pub async fn query_as<T>(
+        &self,
+        sql: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<T>, DbClientError>
+    where
+        T: for<'r> sqlx::FromRow<'r, B::Row>,
+    {
+        let rows = self.fetch_with(sql, params).await?;
+        rows.iter()
+            .map(|row| T::from_row(row))
+            .collect::<Result<_, _>>()
+            .map_err(DbClientError::Fetch)
+    }
+
+    /// Reads the rows of `table` selected by `query`.
+    pub async fn query_table(
+        &self,
+        table: &str,
+        query: &TableQuery,
+    ) -> Result<Vec<serde_json::Value>, DbClientError> {
+        let mut builder = query::select::<B>(table, query)?;
+        let mut conn = self.acquire().await?;
+        let rows = B::fetch_all(&mut conn, &mut builder)
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(None, table, e))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| serde_json::Value::Object(B::row_to_json(row)))
+            .collect())
+    }
+
+    /// Reads the rows written for `topic` selected by `query`, from the
+    /// first table mapped to the topic. The time column defaults to the
+    /// `received_at` metadata column of that mapping.
+    pub async fn query_topic(
+        &self,
+        topic: &str,
+        query: &TableQuery,
+    ) -> Result<Vec<serde_json::Value>, DbClientError> {
+        let mapping = self
+            .topic_table_map
+            .get(topic)
+            .and_then(|m| m.tables.first())
+            .ok_or_else(|| DbClientError::UnknownMapping {
+                topic: topic.to_string(),
+            })?;
+
+        if query.time_column.is_some() {
+            return self.query_table(&mapping.table, query).await;
+        }
+        let query = TableQuery {
+            time_column: mapping.metadata.received_at.clone(),
+            ..query.clone()
+        };
+        self.query_table(&mapping.table, &query).await
+    }
+
+    async fn fetch_with(
+        &self,
+        sql: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<B::Row>, DbClientError> {
+        let mut conn = self.acquire().await?;
+        B::fetch_with(&mut conn, sql, params)
+            .await
+            .map_err(DbClientError::Fetch)
+    }
+
+    async fn acquire(&self) -> Result<sqlx::pool::PoolConnection<B>, DbClientError> {
+        self.connection_pool
+            .acquire()
+            .await
+            .map_err(DbClientError::Connection)
+    }
+
+    pub async fn push(&self, topic: &str, payload: &str) -> Result<(), DbClientError> {
+        self.push_with_info(&MessageInfo::new(topic), payload.as_bytes())
+            .await
+    }
+
+    /// Pushes a message received by the client `client_id`, e.g. that of
+    /// [`MqttClient::client_id`], making its properties available to the
+    /// field rules of its mapping.
+    pub async fn push_message(
+        &self,
+        msg: &paho_mqtt::Message,
+        client_id: &str,
+    ) -> Result<(), DbClientError> {
+        let info = MessageInfo::from(msg).with_client_id(client_id);
+        self.push_with_info(&info, msg.payload()).await
+    }
+
+    /// Writes the payload to every table mapped to the topic, within a
+    /// single transaction if there are several.
+    ///
+    /// Writes failing with a transient error are retried, then the message
+    /// is buffered until the database is available again, after which it is
+    /// written in order with the messages pushed meanwhile. Buffered
+    /// messages count as pushed. On any other failure, or with the buffer
+    /// full, the message is recorded in the configured dead letter store.
+    pub async fn push_with_info(
+        &self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+    ) -> Result<(), DbClientError> {
+        if self.is_buffering().await {
+            // Queued behind the buffered messages to keep their order.
+            return match self.queue(info, payload).await {
+                true => Ok(()),
+                false => Err(DbClientError::BufferFull),
+            };
+        }
+
+        match self.write_with_retry(info, payload).await {
+            Ok(()) => Ok(()),
+            Err(e) if self.defer(info, payload, &e).await => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Buffers a message that failed to be written with a transient error,
+    /// or dead letters it, returning whether it was buffered.
+    pub(crate) async fn defer(
+        &self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+        error: &DbClientError,
+    ) -> bool {
+        if error.is_transient() {
+            let mut buffer = self.buffer.lock().await;
+            if self.buffer_message(&mut buffer, info, payload, error) {
+                return true;
+            }
+        }
+        self.dead_letter(info, payload, error).await;
+        false
+    }
+
+    /// Whether messages are buffered, and still are after draining them if
+    /// the backoff has passed, in which case new ones are queued behind.
+    pub(crate) async fn is_buffering(&self) -> bool {
+        let mut buffer = self.buffer.lock().await;
+        !buffer.is_empty() && !self.drain_if_due(&mut buffer).await
+    }
+
+    /// Buffers a message behind the buffered ones, or dead letters it if
+    /// the buffer is full, returning whether it was buffered.
+    pub(crate) async fn queue(&self, info: &MessageInfo<'_>, payload: &[u8]) -> bool {
+        let mut buffer = self.buffer.lock().await;
+        if self.buffer_message(&mut buffer, info, payload, "database unavailable") {
+            return true;
+        }
+        self.dead_letter(info, payload, &DbClientError::BufferFull)
+            .await;
+        false
+    }
+
+    /// Writes the buffered messages in order, returning how many were
+    /// written. Stops at the first transient failure, which is returned,
+    /// while messages failing otherwise are dead lettered.
+    ///
+    /// Pushing a message also writes the buffered ones, once the backoff
+    /// since the last attempt has passed; this is for when no messages
+    /// arrive.
+    pub async fn drain_buffer(&self) -> Result<usize, DbClientError> {
+        let mut buffer = self.buffer.lock().await;
+        self.drain(&mut buffer).await
+    }
+
+    /// Messages waiting for the database.
+    pub async fn buffered(&self) -> usize {
+        self.buffer.lock().await.len()
+    }
+
+    /// Drains the buffer if the backoff has passed, returning whether it is
+    /// empty.
+    async fn drain_if_due(&self, buffer: &mut retry::Buffer) -> bool {
+        if !buffer.is_due() {
+            return false;
+        }
+        self.drain(buffer).await.is_ok() && buffer.is_empty()
+    }
+
+    async fn drain(&self, buffer: &mut retry::Buffer) -> Result<usize, DbClientError> {
+        let mut written = 0;
+        loop {
+            let letter = match buffer.front() {
+                Ok(Some(letter)) => letter.clone(),
+                Ok(None) => break,
+                Err(e) => return Err(DbClientError::Spool(e)),
+            };
+            match self.write(&letter.info(), &letter.payload).await {
+                Ok(()) => written += 1,
+                Err(e) if e.is_transient() => {
+                    buffer.failures += 1;
+                    let delay = retry::backoff(&self.retry, buffer.failures);
+                    buffer.next_drain = Some(tokio::time::Instant::now() + delay);
+                    log::warn!(
+                        "Database unavailable, {} messages buffered, retrying in {:?}: {}",
+                        buffer.len(),
+                        delay,
+                        e
+                    );
+                    return Err(e);
+                }
+                Err(e) => self.dead_letter(&letter.info(), &letter.payload, &e).await,
+            }
+            buffer.pop_front();
+        }
+
+        buffer.failures = 0;
+        buffer.next_drain = None;
+        if written > 0 {
+            log::info!("Wrote {} buffered messages", written);
+        }
+        Ok(written)
+    }
+
+    /// Adds the message to the buffer, `false` if it is full.
+    fn buffer_message(
+        &self,
+        buffer: &mut retry::Buffer,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+        reason: impl std::fmt::Display,
+    ) -> bool {
+        let was_empty = buffer.is_empty();
+        match buffer.push(DeadLetter::new(info, payload, &reason)) {
+            Ok(buffered) => {
+                if buffered && was_empty {
+                    buffer.failures = 1;
+                    buffer.next_drain =
+                        Some(tokio::time::Instant::now() + retry::backoff(&self.retry, 1));
+                    log::warn!("Buffering messages: {}", reason);
+                }
+                buffered
+            }
+            Err(e) => {
+                log::error!("Failed to spool message on '{}': {}", info.topic, e);
+                false
+            }
+        }
+    }
+
+    async fn write_with_retry(
+        &self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+    ) -> Result<(), DbClientError> {
+        let mut failures = 0;
+        loop {
+            match self.write(info, payload).await {
+                Err(e) if e.is_transient() && failures + 1 < self.retry.max_attempts => {
+                    failures += 1;
+                    let delay = retry::backoff(&self.retry, failures);
+                    log::warn!("Retrying write on '{}' in {:?}: {}", info.topic, delay, e);
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Inserts `rows` into `table`, retrying transient failures as
+    /// configured.
+    pub(crate) async fn insert_with_retry(
+        &self,
+        topic: Option<&str>,
+        table: &str,
+        mode: &setup_config::sql_server::WriteMode,
+        columns: &[String],
+        rows: &[Vec<SqlValue>],
+    ) -> Result<u64, DbClientError> {
+        let mut failures = 0;
+        loop {
+            match self
+                .insert_pooled(topic, table, mode, columns, rows.to_vec())
+                .await
+            {
+                Err(e) if e.is_transient() && failures + 1 < self.retry.max_attempts => {
+                    failures += 1;
+                    let delay = retry::backoff(&self.retry, failures);
+                    log::warn!("Retrying insert into '{}' in {:?}: {}", table, delay, e);
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Records the message in the dead letter store, if any, logging the
+    /// outcome.
+    async fn dead_letter(&self, info: &MessageInfo<'_>, payload: &[u8], error: &DbClientError) {
+        if self.dead_letter.is_none() {
+            return;
+        }
+        let letter = DeadLetter::new(info, payload, error);
+        match self.record_dead_letter(&letter).await {
+            Ok(()) => log::warn!("Dead lettered message on '{}': {}", info.topic, error),
+            Err(err) => log::error!("Failed to dead letter message on '{}': {}", info.topic, err),
+        }
+    }
+
+    async fn write(&self, info: &MessageInfo<'_>, payload: &[u8]) -> Result<(), DbClientError> {
+        let topic = info.topic;
+        let mut rows = self.to_rows(info, payload).await?;
+
+        if rows.len() == 1 {
+            let (mapping, row) = rows.remove(0);
+            self.insert_pooled(
+                Some(topic),
+                &mapping.table,
+                &mapping.write,
+                &row.columns,
+                vec![row.values],
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let first_table = rows.first().map_or("", |(m, _)| m.table.as_str());
+        let mut tx = self
+            .connection_pool
+            .begin()
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(Some(topic), first_table, e))?;
+
+        for (mapping, row) in rows.iter() {
+            self.insert(
+                &mut tx,
+                Some(topic),
+                &mapping.table,
+                &mapping.write,
+                &row.columns,
+                vec![row.values.clone()],
+            )
+            .await?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(Some(topic), first_table, e))
+    }
+
+    /// Sets the client publishing dead letters to a
+    /// [`DeadLetterStore::Republish`] store. Only the first client set is
+    /// used.
+    pub fn set_dead_letter_publisher(&self, client: MqttClient) {
+        let _ = self.dead_letter_publisher.set(client);
+    }
+
+    /// Records `letter` in the configured dead letter store, doing nothing
+    /// without one.
+    pub async fn record_dead_letter(&self, letter: &DeadLetter) -> Result<(), DbClientError> {
+        match &self.dead_letter {
+            None => Ok(()),
+            Some(DeadLetterStore::Table { table }) => {
+                self.create_dead_letter_table(table).await?;
+                let failed_at = letter.failed_at.to_offset(time::UtcOffset::UTC);
+                let values = vec![
+                    SqlValue::DateTime(time::PrimitiveDateTime::new(
+                        failed_at.date(),
+                        failed_at.time(),
+                    )),
+                    SqlValue::Text(letter.topic.clone()),
+                    SqlValue::Text(letter.error.clone()),
+                    SqlValue::Json(letter.to_json()),
+                ];
+                let columns = dead_letter::COLUMNS.map(str::to_string);
+                self.insert_pooled(
+                    Some(&letter.topic),
+                    table,
+                    &WriteMode::Append,
+                    &columns,
+                    vec![values],
+                )
+                .await
+                .map(|_| ())
+            }
+            Some(DeadLetterStore::File { path }) => {
+                let _guard = self.dead_letter_file.lock().await;
+                dead_letter::append_file(path, letter).map_err(DbClientError::DeadLetterFile)
+            }
+            Some(DeadLetterStore::Republish { topic, qos }) => {
+                let client =
+                    self.dead_letter_publisher
+                        .get()
+                        .ok_or(DbClientError::DeadLetterPublish(paho_mqtt::Error::General(
+                            "no dead letter publisher set",
+                        )))?;
+                let msg = paho_mqtt::Message::new(topic, letter.to_json().to_string(), *qos);
+                client
+                    .publish(msg)
+                    .await
+                    .map_err(DbClientError::DeadLetterPublish)
+            }
+        }
+    }
+
+    /// Writes the stored dead letters again, e.g. once the mapping or schema
+    /// is fixed, removing those written.
+    ///
+    /// Dead letters republished to MQTT are replayed as they are received
+    /// instead, with [`DeadLetter::from_message`] and
+    /// [`Self::replay_dead_letter`].
+    pub async fn replay_dead_letters(&self) -> Result<ReplayReport, DbClientError> {
+        match &self.dead_letter {
+            Some(DeadLetterStore::Table { table }) => self.replay_dead_letter_table(table).await,
+            Some(DeadLetterStore::File { path }) => self.replay_dead_letter_file(path).await,
+            Some(DeadLetterStore::Republish { .. }) | None => Err(DbClientError::Unsupported),
+        }
+    }
+
+    /// Writes `letter` like the message it holds, without recording it again
+    /// on failure.
+    pub async fn replay_dead_letter(&self, letter: &DeadLetter) -> Result<(), DbClientError> {
+        self.write(&letter.info(), &letter.payload).await
+    }
+
+    /// Replays a stored record, `false` if it is kept in the store.
+    async fn replay_record(&self, record: &serde_json::Value) -> bool {
+        let Some(letter) = DeadLetter::from_json(record) else {
+            log::warn!("Skipping malformed dead letter: {}", record);
+            return false;
+        };
+        match self.replay_dead_letter(&letter).await {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("Replay of dead letter on '{}' failed: {}", letter.topic, e);
+                false
+            }
+        }
+    }
+
+    async fn replay_dead_letter_file(&self, path: &Path) -> Result<ReplayReport, DbClientError> {
+        let _guard = self.dead_letter_file.lock().await;
+        let lines = dead_letter::read_file(path).map_err(DbClientError::DeadLetterFile)?;
+
+        let mut report = ReplayReport::default();
+        let mut kept = Vec::new();
+        for line in lines {
+            if self
+                .replay_record(&serde_json::Value::from(line.as_str()))
+                .await
+            {
+                report.replayed += 1;
+            } else {
+                report.failed += 1;
+                kept.push(line);
+            }
+        }
+
+        if report.replayed > 0 {
+            dead_letter::rewrite_file(path, &kept).map_err(DbClientError::DeadLetterFile)?;
+        }
+        Ok(report)
+    }
+
+    async fn replay_dead_letter_table(&self, table: &str) -> Result<ReplayReport, DbClientError> {
+        /// Records read at a time.
+        const PAGE: usize = 100;
+
+        self.create_dead_letter_table(table).await?;
+        let quote = |name: &str| identifier::quote(name, B::DIALECT.quote).unwrap_or_default();
+        let (quoted_table, id) = (quote(table), quote("id"));
+
+        let mut report = ReplayReport::default();
+        let mut last_id = None;
+        loop {
+            let mut select = sqlx::QueryBuilder::<B>::new(format!(
+                "SELECT {}, {} FROM {}",
+                id,
+                quote("record"),
+                quoted_table
+            ));
+            if let Some(last_id) = last_id {
+                select.push(format!(" WHERE {} > ", id));
+                B::push_bind(&mut select.separated(""), SqlValue::Int(last_id));
+            }
+            select.push(format!(" ORDER BY {} LIMIT {}", id, PAGE));
+
+            let rows: Vec<_> = {
+                let mut conn = self.acquire().await?;
+                B::fetch_all(&mut conn, &mut select)
+                    .await
+                    .map_err(|e| DbClientError::from_query::<B>(None, table, e))?
+                    .iter()
+                    .map(B::row_to_json)
+                    .collect()
+            };
+            if rows.is_empty() {
+                return Ok(report);
+            }
+
+            for row in rows {
+                let Some(row_id) = row.get("id").and_then(serde_json::Value::as_i64) else {
+                    report.failed += 1;
+                    continue;
+                };
+                last_id = Some(row_id);
+
+                let record = row.get("record").cloned().unwrap_or_default();
+                if !self.replay_record(&record).await {
+                    report.failed += 1;
+                    continue;
+                }
+
+                let mut delete = sqlx::QueryBuilder::<B>::new(format!(
+                    "DELETE FROM {} WHERE {} = ",
+                    quoted_table, id
+                ));
+                B::push_bind(&mut delete.separated(""), SqlValue::Int(row_id));
+                let mut conn = self.acquire().await?;
+                B::execute(&mut conn, &mut delete)
+                    .await
+                    .map_err(|e| DbClientError::from_query::<B>(None, table, e))?;
+                report.replayed += 1;
+            }
+        }
+    }
+
+    async fn create_dead_letter_table(&self, table: &str) -> Result<(), DbClientError> {
+        self.dead_letter_table
+            .get_or_try_init(|| async {
+                let sql = dead_letter::create_table(table, &B::DIALECT).ok_or_else(|| {
+                    DbClientError::InvalidIdentifier {
+                        topic: None,
+                        identifier: table.to_string(),
+                    }
+                })?;
+                let mut conn = self.acquire().await?;
+                B::execute(&mut conn, &mut sqlx::QueryBuilder::new(sql))
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| DbClientError::from_query::<B>(None, table, e))
+            })
+            .await
+            .map(|_| ())
+    }
+
+    /// Decodes `payload` into the rows to write with each table mapping of
+    /// the topic of the message.
+    pub(crate) async fn to_rows(
+        &self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+    ) -> Result<Vec<(&setup_config::sql_server::TableMapping, Row)>, DbClientError> {
+        let topic = info.topic;
+        let payload_any: serde_json::Value = match serde_json::from_slice(payload) {
+            Ok(o) => o,
+            Err(e) => return Err(DbClientError::MqttPayload(e)),
+        };
+
+        let mappings = match self.topic_table_map.get(topic) {
+            Some(m) => &m.tables,
+            None => {
+                return Err(DbClientError::UnknownMapping {
+                    topic: topic.to_string(),
+                })
+            }
+        };
+
+        let mut out = Vec::with_capacity(mappings.len());
+        for mapping in mappings {
+            let row = self.to_row(mapping, payload_any.clone(), info).await?;
+            out.push((mapping, row));
+        }
+        Ok(out)
+    }
+
+    async fn to_row(
+        &self,
+        mapping: &setup_config::sql_server::TableMapping,
+        payload: serde_json::Value,
+        info: &MessageInfo<'_>,
+    ) -> Result<Row, DbClientError> {
+        let topic = info.topic;
+        let table = mapping.table.as_str();
+
+        let mut row = mapping::apply(mapping, payload, info, &self.nested_values)?;
+
+        if let Some(column) = row.columns.iter().find(|c| !identifier::validate(c)) {
+            return Err(DbClientError::InvalidIdentifier {
+                topic: Some(topic.to_string()),
+                identifier: column.clone(),
+            });
+        }
+
+        if self.uses_schema() {
+            self.apply_schema(topic, table, &mut row).await?;
+        }
+
+        if let Some(keys) = mapping.write.keys() {
+            if let Some(key) = keys
+                .iter()
+                .find(|k| !row.columns.iter().any(|c| B::DIALECT.same_column(c, k)))
+            {
+                return Err(DbClientError::MissingKey {
+                    topic: topic.to_string(),
+                    table: table.to_string(),
+                    column: key.clone(),
+                });
+            }
+        }
+
+        Ok(row)
+    }
+
+    /// Applies the configured [`setup_config::sql_server::UnknownColumns`]
+    /// policy to the columns of `row`, and coerces its values to the column
+    /// types.
+    async fn apply_schema(
+        &self,
+        topic: &str,
+        table: &str,
+        row: &mut Row,
+    ) -> Result<(), DbClientError> {
+        use setup_config::sql_server::UnknownColumns;
+
+        let mut schema = self
+            .schema
+            .table(&self.connection_pool, table)
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(Some(topic), table, e))?;
+
+        if self.schema_evolution.is_enabled() {
+            schema = self.evolve_schema(topic, table, row, schema).await?;
+        }
+
+        let mut i = 0;
+        while i < row.columns.len() {
+            let Some(column_type) = schema.column(&row.columns[i]) else {
+                match self.unknown_columns {
+                    UnknownColumns::Allow => {
+                        i += 1;
+                        continue;
+                    }
+                    UnknownColumns::Reject => {
+                        return Err(DbClientError::UnknownColumn {
+                            topic: topic.to_string(),
+                            table: table.to_string(),
+                            column: row.columns.swap_remove(i),
+                        })
+                    }
+                    UnknownColumns::Drop => {
+                        log::debug!(
+                            "Dropping unknown column '{}' of '{}' for '{}'",
+                            row.columns[i],
+                            table,
+                            topic
+                        );
+                        row.columns.remove(i);
+                        row.values.remove(i);
+                        continue;
+                    }
+                }
+            };
+
+            if self.coerce_types {
+                let value = std::mem::replace(&mut row.values[i], SqlValue::Null);
+                row.values[i] = coerce::coerce(value, column_type).map_err(|reason| {
+                    DbClientError::Coercion {
+                        topic: topic.to_string(),
+                        table: table.to_string(),
+                        column: row.columns[i].clone(),
+                        reason,
+                    }
+                })?;
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Creates `table` or adds the columns of `row` missing from it, as
+    /// configured, returning the up to date schema.
+    async fn evolve_schema(
+        &self,
+        topic: &str,
+        table: &str,
+        row: &mut Row,
+        schema: Arc<TableSchema>,
+    ) -> Result<Arc<TableSchema>, DbClientError> {
+        let mut missing = Vec::new();
+        let mut i = 0;
+        while i < row.columns.len() {
+            match schema.column(&row.columns[i]) {
+                Some(column_type) => {
+                    if evolve::is_narrowing(column_type, &row.values[i]) {
+                        return Err(DbClientError::NarrowingChange {
+                            topic: topic.to_string(),
+                            table: table.to_string(),
+                            column: row.columns.swap_remove(i),
+                        });
+                    }
+                }
+                None => match evolve::infer_type(&row.values[i], &B::DIALECT.types) {
+                    Some(ty) => missing.push((row.columns[i].clone(), ty)),
+                    None => {
+                        // A NULL is the same as leaving the column out, and
+                        // its type is only known once a value shows up.
+                        row.columns.remove(i);
+                        row.values.remove(i);
+                        continue;
+                    }
+                },
+            }
+            i += 1;
+        }
+
+        if missing.is_empty() {
+            return Ok(schema);
+        }
+
+        let statements = if schema.columns.is_empty() {
+            if !self.schema_evolution.create_tables {
+                return Ok(schema);
+            }
+            evolve::create_table(table, &missing, &B::DIALECT).map(|ddl| vec![ddl])
+        } else {
+            if !self.schema_evolution.add_columns {
+                return Ok(schema);
+            }
+            evolve::add_columns(table, &missing, &B::DIALECT)
+        };
+        let statements = statements.ok_or_else(|| DbClientError::InvalidIdentifier {
+            topic: Some(topic.to_string()),
+            identifier: table.to_string(),
+        })?;
+
+        if self.schema_evolution.dry_run {
+            for ddl in statements {
+                if self.dry_run_logged.lock().unwrap().insert(ddl.clone()) {
+                    log::info!("Dry run, not executing: {}", ddl);
+                }
+            }
+            return Ok(schema);
+        }
+
+        let mut conn = self.acquire().await?;
+        for ddl in statements {
+            log::info!("Evolving schema of '{}': {}", table, ddl);
+            if let Err(e) = B::execute(&mut conn, &mut sqlx::QueryBuilder::new(ddl)).await {
+                // Another task may have added the same column in the meantime.
+                let duplicate =
+                    matches!(&e, sqlx::Error::Database(err) if B::is_duplicate_column(&**err));
+                if !duplicate {
+                    return Err(DbClientError::from_query::<B>(Some(topic), table, e));
+                }
+            }
+        }
+        drop(conn);
+
+        self.schema
+            .reload(&self.connection_pool, table)
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(Some(topic), table, e))
+    }
+
+    /// Writes `rows` with [`Self::insert`] on a connection of the pool.
+    pub(crate) async fn insert_pooled(
+        &self,
+        topic: Option<&str>,
+        table: &str,
+        mode: &setup_config::sql_server::WriteMode,
+        columns: &[String],
+        rows: Vec<Vec<SqlValue>>,
+    ) -> Result<u64, DbClientError> {
+        let mut conn = self.acquire().await?;
+        self.insert(&mut conn, topic, table, mode, columns, rows)
+            .await
+    }
+
+    /// Writes `rows` sharing the same `columns` with a single statement,
+    /// returning the number of affected rows.
+    pub(crate) async fn insert(
+        &self,
+        conn: &mut B::Connection,
+        topic: Option<&str>,
+        table: &str,
+        mode: &setup_config::sql_server::WriteMode,
+        columns: &[String],
+        rows: Vec<Vec<SqlValue>>,
+    ) -> Result<u64, DbClientError> {
+        let (head, tail) =
+            write::statement(table, columns, mode, &B::DIALECT).map_err(|e| match e {
+                StatementError::InvalidIdentifier(identifier) => DbClientError::InvalidIdentifier {
+                    topic: topic.map(str::to_string),
+                    identifier,
+                },
+                StatementError::UnsupportedMode => DbClientError::Unsupported,
+            })?;
+
+        let mut builder = sqlx::QueryBuilder::<B>::new(head);
+        builder.push_values(rows, |mut insert, values| {
+            for v in values {
+                B::push_bind(&mut insert, v);
+            }
+        });
+        builder.push(tail);
+
+        B::execute(conn, &mut builder)
+            .await
+            .map_err(|e| DbClientError::from_query::<B>(topic, table, e))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use crate::setup_config::SqlServerSetupConfig;
+    use crate::{
+        BatchConfig, DbClientError, MessageInfo, SqlValue, SqliteBatchWriter, SqliteClient,
+    };
+    use crate::{Order, Page, TableQuery};
+    use serde_json::{json, Map, Value};
+    use time::macros::datetime;
+    use time::Duration;
+
+    /// A client of a fresh database in memory, configured by `config` on top
+    /// of the SQLite driver.
+    async fn client(config: Value) -> SqliteClient {
+        let mut base = json!({"driver": "sqlite", "database": ":memory:"});
+        base.as_object_mut()
+            .unwrap()
+            .extend(config.as_object().unwrap().clone());
+        let config: SqlServerSetupConfig = serde_json::from_value(base).unwrap();
+        SqliteClient::start(config.into()).await.unwrap()
+    }
+
+    async fn execute(client: &SqliteClient, sql: &str) {
+        sqlx::query(sql)
+            .execute(&client.connection_pool)
+            .await
+            .unwrap();
+    }
+
+    async fn select(client: &SqliteClient, sql: &str) -> Vec<Map<String, Value>> {
+        let rows = client.query(sql, Vec::new()).await.unwrap();
+        rows.into_iter()
+            .map(|row| row.as_object().unwrap().clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn forwards_payloads_with_coercion() {
+        let client = client(json!({"topic_table_map": {"sensors/temp": "readings"}})).await;
+        execute(
+            &client,
+            "CREATE TABLE readings (device TEXT, temp REAL, active BOOLEAN, at DATETIME)",
+        )
+        .await;
+        client.refresh_schema().await.unwrap();
+
+        client
+            .push(
+                "sensors/temp",
+                r#"{"device": "d1", "temp": "21.5", "active": 1, "at": "2024-03-01T12:00:00Z"}"#,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            select(&client, "SELECT * FROM readings").await,
+            vec![json!({
+                "device": "d1",
+                "temp": 21.5,
+                "active": true,
+                "at": "2024-03-01 12:00:00.0",
+            })
+            .as_object()
+            .unwrap()
+            .clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn records_the_receiving_client() {
+        let client = client(json!({"topic_table_map": {"t": {
+            "table": "events",
+            "metadata": {"client_id": "received_by"},
+        }}}))
+        .await;
+        execute(&client, "CREATE TABLE events (a INTEGER, received_by TEXT)").await;
+
+        let msg = paho_mqtt::Message::new("t", r#"{"a": 1}"#, 1);
+        client.push_message(&msg, "forwarder-1").await.unwrap();
+
+        assert_eq!(
+            Value::from(select(&client, "SELECT * FROM events").await),
+            json!([{"a": 1, "received_by": "forwarder-1"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn evolves_the_schema() {
+        let client = client(json!({
+            "topic_table_map": {"t": "events"},
+            "schema_evolution": {"create_tables": true, "add_columns": true},
+        }))
+        .await;
+
+        client.push("t", r#"{"a": 1}"#).await.unwrap();
+        client
+            .push("t", r#"{"a": 2, "b": "x", "c": 1.5}"#)
+            .await
+            .unwrap();
+
+        let rows = select(&client, "SELECT a, b, c FROM events ORDER BY a").await;
+        assert_eq!(
+            Value::from(rows),
+            json!([
+                {"a": 1, "b": null, "c": null},
+                {"a": 2, "b": "x", "c": 1.5},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn fans_out_within_a_transaction() {
+        let client = client(json!({
+            "coerce_types": false,
+            "topic_table_map": {
+                "t": [
+                    "archive",
+                    {"table": "state", "write": {"upsert": {"keys": ["device"]}}},
+                ],
+                "broken": ["archive", "missing"],
+            },
+        }))
+        .await;
+        execute(&client, "CREATE TABLE archive (device TEXT, temp REAL)").await;
+        execute(
+            &client,
+            "CREATE TABLE state (device TEXT PRIMARY KEY, temp REAL)",
+        )
+        .await;
+
+        for temp in [20.0, 21.0] {
+            let payload = json!({"device": "d1", "temp": temp}).to_string();
+            client.push("t", &payload).await.unwrap();
+        }
+        assert_eq!(
+            Value::from(select(&client, "SELECT * FROM state").await),
+            json!([{"device": "d1", "temp": 21.0}])
+        );
+
+        let err = client
+            .push("broken", r#"{"device": "d2", "temp": 1.0}"#)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, DbClientError::Query { table, .. } if table == "missing"));
+        assert_eq!(
+            Value::from(select(&client, "SELECT device FROM archive").await),
+            json!([{"device": "d1"}, {"device": "d1"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn flushes_batches() {
+        let client = client(json!({
+            "topic_table_map": {"t": "events"},
+            "schema_evolution": {"create_tables": true},
+        }))
+        .await;
+        // Creates the table, which a batch does not.
+        client.push("t", r#"{"n": 0}"#).await.unwrap();
+
+        let mut writer = SqliteBatchWriter::new(&client, BatchConfig::default());
+        for n in 1..=3 {
+            let payload = json!({ "n": n }).to_string();
+            assert!(writer.push("t", &payload).await.unwrap().is_empty());
+        }
+        let reports = writer.flush().await;
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].is_ok());
+        assert_eq!(reports[0].inserted, 3);
+
+        assert_eq!(
+            Value::from(select(&client, "SELECT count(*) AS n FROM events").await),
+            json!([{"n": 4}])
+        );
+    }
+
+    #[tokio::test]
+    async fn dead_letters_failed_batch_rows() {
+        let client = client(json!({
+            "topic_table_map": {"t": "events"},
+            "dead_letter": {"table": {"table": "dead_letters"}},
+        }))
+        .await;
+        execute(&client, "CREATE TABLE events (n INTEGER CHECK (n > 0))").await;
+
+        let mut writer = SqliteBatchWriter::new(&client, BatchConfig::default());
+        for n in [1, -1, 2] {
+            let payload = json!({ "n": n }).to_string();
+            writer.push("t", &payload).await.unwrap();
+        }
+        let reports = writer.flush().await;
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].batch_error.is_some());
+        assert_eq!((reports[0].inserted, reports[0].buffered), (2, 0));
+        assert_eq!(reports[0].failed.len(), 1);
+
+        assert_eq!(
+            Value::from(select(&client, "SELECT n FROM events ORDER BY n").await),
+            json!([{"n": 1}, {"n": 2}])
+        );
+        assert_eq!(
+            Value::from(select(&client, "SELECT topic FROM dead_letters").await),
+            json!([{"topic": "t"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn dead_letters_fan_out_messages_once() {
+        let client = client(json!({
+            "topic_table_map": {"fan": ["events", "copies"]},
+            "dead_letter": {"table": {"table": "dead_letters"}},
+        }))
+        .await;
+        execute(&client, "CREATE TABLE events (n INTEGER CHECK (n > 0))").await;
+        execute(&client, "CREATE TABLE copies (n INTEGER CHECK (n > 0))").await;
+
+        let mut writer = SqliteBatchWriter::new(&client, BatchConfig::default());
+        writer.push("fan", r#"{"n": -1}"#).await.unwrap();
+        let reports = writer.flush().await;
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.failed.len() == 1));
+
+        assert_eq!(
+            Value::from(select(&client, "SELECT topic FROM dead_letters").await),
+            json!([{"topic": "fan"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn queues_batches_behind_buffered_messages() {
+        let client = client(json!({
+            "topic_table_map": {"t": "events"},
+            "retry": {"initial_backoff_ms": 60_000},
+        }))
+        .await;
+        execute(&client, "CREATE TABLE events (n INTEGER)").await;
+        assert!(client.queue(&MessageInfo::new("t"), br#"{"n": 1}"#).await);
+
+        let mut writer = SqliteBatchWriter::new(&client, BatchConfig::default());
+        writer.push("t", r#"{"n": 2}"#).await.unwrap();
+        let reports = writer.flush().await;
+        assert_eq!((reports[0].inserted, reports[0].buffered), (0, 1));
+        assert_eq!(client.buffered().await, 2);
+
+        assert_eq!(client.drain_buffer().await.unwrap(), 2);
+        assert_eq!(
+            Value::from(select(&client, "SELECT n FROM events ORDER BY rowid").await),
+            json!([{"n": 1}, {"n": 2}])
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_back_rows() {
+        let client = client(json!({
+            "topic_table_map": {
+                "t": {"table": "events", "metadata": {"received_at": "at"}},
+            },
+            "schema_evolution": {"create_tables": true},
+        }))
+        .await;
+
+        for n in 1..=3 {
+            let info = MessageInfo {
+                received_at: datetime!(2024-03-01 0:00 UTC) + Duration::hours(n),
+                ..MessageInfo::new("t")
+            };
+            let payload = json!({ "n": n }).to_string();
+            client
+                .push_with_info(&info, payload.as_bytes())
+                .await
+                .unwrap();
+        }
+
+        let query = TableQuery {
+            order: Order::Descending,
+            page: Some(Page::first(1)),
+            ..TableQuery::between(datetime!(2024-03-01 1:30), datetime!(2024-03-02 0:00))
+        };
+        let first = client.query_topic("t", &query).await.unwrap();
+        assert_eq!(first[0]["n"], json!(3));
+        assert_eq!(first[0]["at"], json!("2024-03-01 03:00:00.0"));
+
+        let query = TableQuery {
+            page: query.page.map(Page::next),
+            ..query
+        };
+        let second = client.query_topic("t", &query).await.unwrap();
+        assert_eq!(
+            Value::from(second),
+            json!([{"n": 2, "at": "2024-03-01 02:00:00.0"}])
+        );
+
+        let rows = client
+            .query(
+                "SELECT n FROM events WHERE n > ? ORDER BY n",
+                vec![SqlValue::Int(1)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(Value::from(rows), json!([{"n": 2}, {"n": 3}]));
+
+        let rows: Vec<(i64,)> = client
+            .query_as("SELECT n FROM events ORDER BY n", Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(1,), (2,), (3,)]);
+
+        assert!(matches!(
+            client
+                .query_table(
+                    "events",
+                    &TableQuery::between(datetime!(2024-03-01 0:00), datetime!(2024-03-02 0:00))
+                )
+                .await,
+            Err(DbClientError::MissingTimeColumn { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn replays_dead_letters() {
+        let path =
+            std::env::temp_dir().join(format!("dead-letters-e2e-{}.jsonl", std::process::id()));
+        for store in [
+            json!({"table": {"table": "dead_letters"}}),
+            json!({"file": {"path": path}}),
+        ] {
+            let client = client(json!({
+                "topic_table_map": {"t": "events"},
+                "dead_letter": store,
+            }))
+            .await;
+
+            // The table is missing until the schema is fixed.
+            assert!(client.push("t", r#"{"n": 1}"#).await.is_err());
+            assert!(client.push("t", "not json").await.is_err());
+            let report = client.replay_dead_letters().await.unwrap();
+            assert_eq!((report.replayed, report.failed), (0, 2));
+
+            execute(&client, "CREATE TABLE events (n INTEGER)").await;
+            client.refresh_schema().await.unwrap();
+            let report = client.replay_dead_letters().await.unwrap();
+            assert_eq!((report.replayed, report.failed), (1, 1));
+            assert_eq!(
+                Value::from(select(&client, "SELECT n FROM events").await),
+                json!([{"n": 1}]),
+                "{}",
+                store
+            );
+
+            let report = client.replay_dead_letters().await.unwrap();
+            assert_eq!((report.replayed, report.failed), (0, 1));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn starts_the_configured_driver() {
+        let config: SqlServerSetupConfig = serde_json::from_value(json!({
+            "driver": "sqlite",
+            "database": ":memory:",
+            "topic_table_map": {"t": "events"},
+            "schema_evolution": {"create_tables": true},
+        }))
+        .unwrap();
+
+        let client = crate::start_client(config).await.unwrap();
+        client.push("t", r#"{"n": 1}"#).await.unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use super::schema::ColumnType;
+use super::SqlValue;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, PrimitiveDateTime};
+
+/// Converts `value` to suit a column of type `column`, or describes why it
+/// cannot be.
+///
+/// Values are only converted where the database would otherwise reject or
+/// misinterpret them, everything else is bound as is.
+pub fn coerce(value: SqlValue, column: &ColumnType) -> Result<SqlValue, String> {
+    match (column, value) {
+        (_, SqlValue::Null) => Ok(SqlValue::Null),
+
+        (ColumnType::DateTime, SqlValue::Text(s)) => parse_datetime(&s)
+            .map(SqlValue::DateTime)
+            .ok_or_else(|| format!("'{}' is not a date and time", s)),
+        (ColumnType::DateTime, SqlValue::Int(ms)) => from_epoch_millis(ms as i128),
+        (ColumnType::DateTime, SqlValue::UInt(ms)) => from_epoch_millis(ms as i128),
+        (ColumnType::DateTime, SqlValue::Float(ms)) if !ms.is_finite() => {
+            Err(format!("{} is not a date and time", ms))
+        }
+        (ColumnType::DateTime, SqlValue::Float(ms)) => from_epoch_millis(ms as i128),
+        (ColumnType::DateTime, SqlValue::Bool(_)) => {
+            Err("a boolean is not a date and time".to_string())
+        }
+
+        (ColumnType::Integer { .. }, SqlValue::Bool(b)) => Ok(SqlValue::Int(b as i64)),
+        (ColumnType::Integer { unsigned }, SqlValue::Text(s)) => {
+            let trimmed = s.trim();
+            // Unsigned columns only take `u64`, rejecting negative numbers.
+            let parsed = match unsigned {
+                true => trimmed.parse::<u64>().ok().map(SqlValue::UInt),
+                false => trimmed.parse::<i64>().ok().map(SqlValue::Int),
+            };
+            if let Some(v) = parsed {
+                Ok(v)
+            } else if trimmed.eq_ignore_ascii_case("true") {
+                Ok(SqlValue::Int(1))
+            } else if trimmed.eq_ignore_ascii_case("false") {
+                Ok(SqlValue::Int(0))
+            } else {
+                Err(format!(
+                    "'{}' is not an {}integer",
+                    s,
+                    if *unsigned { "unsigned " } else { "" }
+                ))
+            }
+        }
+        (ColumnType::Integer { unsigned: true }, SqlValue::Int(v)) if v < 0 => {
+            Err(format!("{} is negative", v))
+        }
+
+        (ColumnType::Float | ColumnType::Decimal, SqlValue::Bool(b)) => Ok(SqlValue::Int(b as i64)),
+        (ColumnType::Float, SqlValue::Text(s)) => s
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .map(SqlValue::Float)
+            .ok_or_else(|| format!("'{}' is not a number", s)),
+        // Kept as text so the database keeps every digit of precision.
+        (ColumnType::Decimal, SqlValue::Text(s)) => match s.trim().parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(SqlValue::Decimal(s.trim().to_string())),
+            _ => Err(format!("'{}' is not a number", s)),
+        },
+
+        (ColumnType::Boolean, SqlValue::Int(v @ (0 | 1))) => Ok(SqlValue::Bool(v == 1)),
+        (ColumnType::Boolean, SqlValue::UInt(v @ (0 | 1))) => Ok(SqlValue::Bool(v == 1)),
+        (ColumnType::Boolean, SqlValue::Text(s)) => match s.trim() {
+            t if t.eq_ignore_ascii_case("true") || t == "1" => Ok(SqlValue::Bool(true)),
+            t if t.eq_ignore_ascii_case("false") || t == "0" => Ok(SqlValue::Bool(false)),
+            _ => Err(format!("'{}' is not a boolean", s)),
+        },
+        (ColumnType::Boolean, v @ (SqlValue::Int(_) | SqlValue::UInt(_) | SqlValue::Float(_))) => {
+            Err(format!("{:?} is not a boolean", v))
+        }
+
+        // Scalars as JSON documents, text as the document it holds if any.
+        (ColumnType::Json, SqlValue::Text(s)) => Ok(SqlValue::Json(
+            serde_json::from_str(&s).unwrap_or(serde_json::Value::String(s)),
+        )),
+        (ColumnType::Json, SqlValue::Bool(b)) => Ok(SqlValue::Json(b.into())),
+        (ColumnType::Json, SqlValue::Int(v)) => Ok(SqlValue::Json(v.into())),
+        (ColumnType::Json, SqlValue::UInt(v)) => Ok(SqlValue::Json(v.into())),
+        (ColumnType::Json, SqlValue::Float(v)) => Ok(SqlValue::Json(v.into())),
+
+        (_, value) => Ok(value),
+    }
+}
+
+fn from_epoch_millis(ms: i128) -> Result<SqlValue, String> {
+    ms.checked_mul(1_000_000)
+        .and_then(|ns| OffsetDateTime::from_unix_timestamp_nanos(ns).ok())
+        .map(|t| SqlValue::DateTime(to_utc_primitive(t)))
+        .ok_or_else(|| format!("{} ms since epoch is out of range", ms))
+}
+
+fn to_utc_primitive(t: OffsetDateTime) -> PrimitiveDateTime {
+    let t = t.to_offset(time::UtcOffset::UTC);
+    PrimitiveDateTime::new(t.date(), t.time())
+}
+
+/// Parses RFC 3339 timestamps, converted to UTC, as well as the
+/// `YYYY-MM-DD[ HH:MM:SS[.f]]` form MySQL uses, taken as is.
+fn parse_datetime(s: &str) -> Option<PrimitiveDateTime> {
+    let s = s.trim();
+
+    if let Ok(t) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Some(to_utc_primitive(t));
+    }
+
+    let with_space = format_description!(
+        "[year]-[month]-[day] [hour]:[minute]:[second][optional [.[subsecond]]]"
+    );
+    let with_t = format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second][optional [.[subsecond]]]"
+    );
+    PrimitiveDateTime::parse(s, &with_space)
+        .or_else(|_| PrimitiveDateTime::parse(s, &with_t))
+        .ok()
+        .or_else(|| {
+            Date::parse(s, &format_description!("[year]-[month]-[day]"))
+                .ok()
+                .map(|d| d.midnight())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn coerces_datetimes() {
+        let expected = SqlValue::DateTime(datetime!(2024-03-01 12:30:00));
+        for value in [
+            SqlValue::Text("2024-03-01T20:30:00+08:00".to_string()),
+            SqlValue::Text("2024-03-01 12:30:00".to_string()),
+            SqlValue::Int(1_709_296_200_000),
+        ] {
+            assert_eq!(coerce(value, &ColumnType::DateTime).unwrap(), expected);
+        }
+
+        assert_eq!(
+            coerce(
+                SqlValue::Text("2024-03-01".to_string()),
+                &ColumnType::DateTime
+            )
+            .unwrap(),
+            SqlValue::DateTime(datetime!(2024-03-01 0:00))
+        );
+        assert!(coerce(
+            SqlValue::Text("yesterday".to_string()),
+            &ColumnType::DateTime
+        )
+        .is_err());
+
+        for ms in [f64::MAX, f64::NAN, f64::INFINITY] {
+            assert!(coerce(SqlValue::Float(ms), &ColumnType::DateTime).is_err());
+        }
+        assert!(coerce(SqlValue::Int(i64::MAX), &ColumnType::DateTime).is_err());
+    }
+
+    #[test]
+    fn coerces_numbers() {
+        let int = ColumnType::Integer { unsigned: false };
+        assert_eq!(
+            coerce(SqlValue::Bool(true), &int).unwrap(),
+            SqlValue::Int(1)
+        );
+        assert_eq!(
+            coerce(SqlValue::Text(" 42 ".to_string()), &int).unwrap(),
+            SqlValue::Int(42)
+        );
+        assert!(coerce(SqlValue::Text("4.2".to_string()), &int).is_err());
+        let unsigned = ColumnType::Integer { unsigned: true };
+        assert!(coerce(SqlValue::Int(-1), &unsigned).is_err());
+        assert!(coerce(SqlValue::Text("-1".to_string()), &unsigned).is_err());
+        assert_eq!(
+            coerce(SqlValue::Text("42".to_string()), &unsigned).unwrap(),
+            SqlValue::UInt(42)
+        );
+
+        assert_eq!(
+            coerce(SqlValue::Text("23.7".to_string()), &ColumnType::Float).unwrap(),
+            SqlValue::Float(23.7)
+        );
+        assert_eq!(
+            coerce(
+                SqlValue::Text("0.10000000000000000001".to_string()),
+                &ColumnType::Decimal
+            )
+            .unwrap(),
+            SqlValue::Decimal("0.10000000000000000001".to_string())
+        );
+        assert_eq!(
+            coerce(SqlValue::Int(7), &ColumnType::Text).unwrap(),
+            SqlValue::Int(7)
+        );
+    }
+
+    #[test]
+    fn coerces_booleans_and_json() {
+        assert_eq!(
+            coerce(SqlValue::Int(1), &ColumnType::Boolean).unwrap(),
+            SqlValue::Bool(true)
+        );
+        assert_eq!(
+            coerce(SqlValue::Text("False".to_string()), &ColumnType::Boolean).unwrap(),
+            SqlValue::Bool(false)
+        );
+        assert!(coerce(SqlValue::Int(2), &ColumnType::Boolean).is_err());
+
+        assert_eq!(
+            coerce(SqlValue::Text(r#"{"a": 1}"#.to_string()), &ColumnType::Json).unwrap(),
+            SqlValue::Json(serde_json::json!({"a": 1}))
+        );
+        assert_eq!(
+            coerce(SqlValue::Text("on".to_string()), &ColumnType::Json).unwrap(),
+            SqlValue::Json(serde_json::json!("on"))
+        );
+        assert_eq!(
+            coerce(SqlValue::Float(1.5), &ColumnType::Json).unwrap(),
+            SqlValue::Json(serde_json::json!(1.5))
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use super::dialect::Dialect;
+use super::{identifier, MessageInfo};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Columns of a dead letter table besides its id, the record holding the
+/// whole [`DeadLetter`] and the others kept for querying.
+pub(crate) const COLUMNS: [&str; 4] = ["failed_at", "topic", "error", "record"];
+
+/// A message that could not be written, with why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: i32,
+    pub retained: bool,
+    pub user_properties: Vec<(String, String)>,
+    pub client_id: Option<String>,
+    pub received_at: OffsetDateTime,
+    pub failed_at: OffsetDateTime,
+    pub error: String,
+}
+
+/// Outcome of replaying the stored dead letters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Dead letters written, and removed from the store.
+    pub replayed: usize,
+    /// Dead letters that failed again, or could not be read, and were kept.
+    pub failed: usize,
+}
+
+impl DeadLetter {
+    pub fn new(info: &MessageInfo<'_>, payload: &[u8], error: impl std::fmt::Display) -> Self {
+        DeadLetter {
+            topic: info.topic.to_string(),
+            payload: payload.to_vec(),
+            qos: info.qos,
+            retained: info.retained,
+            user_properties: info.user_properties.clone(),
+            client_id: info.client_id.map(str::to_string),
+            received_at: info.received_at,
+            failed_at: OffsetDateTime::now_utc(),
+            error: error.to_string(),
+        }
+    }
+
+    /// The info of the message as it was first pushed.
+    pub fn info(&self) -> MessageInfo<'_> {
+        MessageInfo {
+            topic: &self.topic,
+            qos: self.qos,
+            retained: self.retained,
+            received_at: self.received_at,
+            user_properties: self.user_properties.clone(),
+            client_id: self.client_id.as_deref(),
+        }
+    }
+
+    /// The record as stored and republished. The payload is kept as text,
+    /// or hex encoded under `payload_hex` if it is not UTF-8.
+    pub fn to_json(&self) -> Value {
+        let mut out = json!({
+            "topic": self.topic,
+            "qos": self.qos,
+            "retained": self.retained,
+            "user_properties": self.user_properties,
+            "client_id": self.client_id,
+            "received_at": format_time(self.received_at),
+            "failed_at": format_time(self.failed_at),
+            "error": self.error,
+        });
+        match std::str::from_utf8(&self.payload) {
+            Ok(text) => out["payload"] = Value::from(text),
+            Err(_) => out["payload_hex"] = Value::from(to_hex(&self.payload)),
+        }
+        out
+    }
+
+    /// Reads a record written by [`Self::to_json`], as text or parsed.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        if let Value::String(text) = value {
+            return Self::from_json(&serde_json::from_str(text).ok()?);
+        }
+
+        let payload = match (&value["payload"], &value["payload_hex"]) {
+            (Value::String(text), _) => text.as_bytes().to_vec(),
+            (_, Value::String(hex)) => from_hex(hex)?,
+            _ => return None,
+        };
+        Some(DeadLetter {
+            topic: value["topic"].as_str()?.to_string(),
+            payload,
+            qos: value["qos"].as_i64().unwrap_or_default() as i32,
+            retained: value["retained"].as_bool().unwrap_or_default(),
+            user_properties: serde_json::from_value(value["user_properties"].clone())
+                .unwrap_or_default(),
+            client_id: value["client_id"].as_str().map(str::to_string),
+            received_at: parse_time(&value["received_at"])?,
+            failed_at: parse_time(&value["failed_at"])?,
+            error: value["error"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Reads a dead letter republished to MQTT.
+    pub fn from_message(msg: &paho_mqtt::Message) -> Option<Self> {
+        Self::from_json(&serde_json::from_slice(msg.payload()).ok()?)
+    }
+}
+
+fn format_time(t: OffsetDateTime) -> Value {
+    t.format(&Rfc3339).map(Value::from).unwrap_or_default()
+}
+
+fn parse_time(value: &Value) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(value.as_str()?, &Rfc3339).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Statement creating the dead letter `table` if it does not exist, or
+/// `None` if its name is not a valid identifier.
+pub(crate) fn create_table(table: &str, dialect: &Dialect) -> Option<String> {
+    let q = |name: &str| identifier::quote(name, dialect.quote);
+    let types = &dialect.types;
+    Some(format!(
+        "CREATE TABLE IF NOT EXISTS {} ({} {}, {} {}, {} {}, {} {}, {} {})",
+        q(table)?,
+        q("id")?,
+        types.id,
+        q("failed_at")?,
+        types.datetime,
+        q("topic")?,
+        types.text,
+        q("error")?,
+        types.text,
+        q("record")?,
+        types.json,
+    ))
+}
+
+/// Appends `letter` to the file at `path` as a line of JSON.
+pub(crate) fn append_file(path: &Path, letter: &DeadLetter) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", letter.to_json())
+}
+
+/// The lines of the file at `path`, none if it does not exist.
+pub(crate) fn read_file(path: &Path) -> std::io::Result<Vec<String>> {
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    std::io::BufReader::new(file)
+        .lines()
+        .filter(|l| !l.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .collect()
+}
+
+/// Replaces the file at `path` with `lines`, through a temporary file so it
+/// is never left partially written.
+pub(crate) fn rewrite_file(path: &Path, lines: &[String]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    {
+        let mut file = std::fs::File::create(&tmp)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+    }
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DbClientError;
+
+    fn letter(payload: &[u8]) -> DeadLetter {
+        let info = MessageInfo {
+            user_properties: vec![("unit".into(), "C".into())],
+            ..MessageInfo::new("sensors/temp").with_client_id("forwarder")
+        };
+        DeadLetter::new(
+            &info,
+            payload,
+            &DbClientError::UnknownMapping {
+                topic: "sensors/temp".into(),
+            },
+        )
+    }
+
+    #[test]
+    fn round_trips_records() {
+        for payload in [&br#"{"temp": 21.5}"#[..], &[0xff, 0x00, 0x7b][..]] {
+            let letter = letter(payload);
+            let record = letter.to_json();
+            assert_eq!(DeadLetter::from_json(&record).unwrap(), letter);
+            assert_eq!(
+                DeadLetter::from_json(&Value::from(record.to_string())).unwrap(),
+                letter
+            );
+        }
+
+        let record = letter(&[0xff, 0x00, 0x7b]).to_json();
+        assert_eq!(record["payload_hex"], json!("ff007b"));
+        assert_eq!(
+            record["error"],
+            json!("map for 'sensors/temp' is not specified")
+        );
+    }
+
+    #[test]
+    fn rewrites_files() {
+        let path = std::env::temp_dir().join(format!("dead-letters-{}.jsonl", std::process::id()));
+        assert!(read_file(&path).unwrap().is_empty());
+
+        append_file(&path, &letter(b"1")).unwrap();
+        append_file(&path, &letter(b"2")).unwrap();
+        let lines = read_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+
+        rewrite_file(&path, &lines[1..]).unwrap();
+        let lines = read_file(&path).unwrap();
+        let letter = DeadLetter::from_json(&Value::from(lines[0].as_str())).unwrap();
+        assert_eq!(letter.payload, b"2");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,427 @@
+//! Journaled delivery from the broker to the database, see [`Forwarder`].
+
+use super::dead_letter::{self, DeadLetter, ReplayReport};
+use super::{DbClient, DbClientError, MessageInfo};
+use crate::MqttReceiver;
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::time::Duration;
+
+/// Interval at which the buffer of the client is written while no messages
+/// arrive.
+const DRAIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages journaled between two attempts to clear the journal, besides
+/// those at every [`DRAIN_INTERVAL`].
+const CHECKPOINT_INTERVAL: usize = 100;
+
+/// Writes received messages to a [`DbClient`], recording them in a journal
+/// until they are written.
+///
+/// Every message is appended to the journal before it is pushed to the
+/// client. The entries of the pushed messages are cleared once none of
+/// them is left in the buffer of the client, every [`CHECKPOINT_INTERVAL`]
+/// messages or [`DRAIN_INTERVAL`]. Messages left in the journal by a crash
+/// are written again at the next start.
+///
+/// This is not at-least-once delivery: paho 0.12 sends the `PUBACK` of a
+/// QoS 1 message as soon as it arrives, before it is handed to the
+/// [`MqttReceiver`], so the broker does not deliver it again if the process
+/// stops before it is journaled, while it waits in the stream of the
+/// receiver. Once journaled, a message is written at least once, and may be
+/// written twice, which a
+/// [`WriteMode::Deduplicate`](crate::setup_config::sql_server::WriteMode)
+/// mapping on a key identifying the message turns into exactly once.
+pub struct Forwarder<'a> {
+    db: &'a dyn DbClient,
+    journal: PathBuf,
+    /// The journal, open for appending once a message is recorded.
+    file: Option<std::fs::File>,
+    /// Entries in the journal.
+    pending: usize,
+    /// Entries at the start of the journal whose message was pushed.
+    pushed: usize,
+    /// The message of the last entry while it is pushed, left set if that
+    /// was interrupted to push it again first.
+    pushing: Option<DeadLetter>,
+}
+
+impl<'a> Forwarder<'a> {
+    /// Forwards to `db` with the journal at `journal`, which is created if
+    /// missing. Call [`Self::recover`] before forwarding new messages.
+    pub fn new(db: &'a dyn DbClient, journal: impl Into<PathBuf>) -> Self {
+        Forwarder {
+            db,
+            journal: journal.into(),
+            file: None,
+            pending: 0,
+            pushed: 0,
+            pushing: None,
+        }
+    }
+
+    /// Writes the messages left in the journal by a previous run, then
+    /// clears it if they are all written.
+    ///
+    /// The messages failing again are dead lettered by the client, as any
+    /// other, and reported as failed.
+    pub async fn recover(&mut self) -> Result<ReplayReport, DbClientError> {
+        let lines = dead_letter::read_file(&self.journal).map_err(DbClientError::Journal)?;
+        self.pending = lines.len();
+        if lines.is_empty() {
+            return Ok(ReplayReport::default());
+        }
+        log::info!("Recovering {} journaled messages", lines.len());
+
+        let mut report = ReplayReport::default();
+        for line in lines {
+            let Some(letter) = DeadLetter::from_json(&serde_json::Value::from(line.as_str()))
+            else {
+                log::warn!("Dropping malformed journal entry: {}", line);
+                report.failed += 1;
+                self.pushed += 1;
+                continue;
+            };
+            match self
+                .db
+                .push_with_info(&letter.info(), &letter.payload)
+                .await
+            {
+                Ok(()) => report.replayed += 1,
+                Err(e) => {
+                    log::error!("Failed to recover message on '{}': {}", letter.topic, e);
+                    report.failed += 1;
+                }
+            }
+            self.pushed += 1;
+        }
+
+        self.checkpoint().await?;
+        Ok(report)
+    }
+
+    /// Records the message in the journal, then pushes it to the client.
+    ///
+    /// The message is pushed even if it cannot be recorded, in which case
+    /// the error of the journal is returned.
+    pub async fn forward(
+        &mut self,
+        info: &MessageInfo<'_>,
+        payload: &[u8],
+    ) -> Result<(), DbClientError> {
+        self.resume().await;
+
+        let letter = DeadLetter::new(info, payload, "not yet written");
+        let journaled = self.append(&letter);
+        if journaled.is_ok() {
+            self.pushing = Some(letter);
+        }
+        let result = self.db.push_with_info(info, payload).await;
+        if self.pushing.take().is_some() {
+            self.pushed += 1;
+        }
+
+        if let (Err(_), Err(e)) = (&journaled, &result) {
+            log::error!("Failed to forward message on '{}': {}", info.topic, e);
+        }
+        if self.pending >= CHECKPOINT_INTERVAL {
+            self.checkpoint().await?;
+        }
+        journaled.and(result)
+    }
+
+    /// Forwards the messages of `receiver` as they arrive, writing the
+    /// buffer of the client in between, until `stop` completes. A message
+    /// being forwarded by then is forwarded to the end.
+    ///
+    /// Dropping the future instead may interrupt the push of a message,
+    /// which is then pushed again before the next one, or at the next
+    /// start.
+    pub async fn run(&mut self, receiver: &mut MqttReceiver, stop: impl Future<Output = ()>) {
+        let client_id = receiver.client().client_id();
+        let mut drain = tokio::time::interval(DRAIN_INTERVAL);
+        tokio::pin!(stop);
+
+        loop {
+            let msg = tokio::select! {
+                biased;
+                _ = &mut stop => return,
+                msg = receiver.poll() => msg,
+                _ = drain.tick() => {
+                    if self.pending > 0 && self.db.drain_buffer().await.is_ok() {
+                        if let Err(e) = self.checkpoint().await {
+                            log::error!("{}", e);
+                        }
+                    }
+                    continue;
+                }
+            };
+            // `None` on disconnection, polling again reconnects.
+            if let Some(msg) = msg {
+                self.forward_message(&msg, &client_id).await;
+            }
+        }
+    }
+
+    /// Forwards the messages already received by `receiver`, without
+    /// waiting for more, e.g. those left once its client is disconnected.
+    pub async fn forward_received(&mut self, receiver: &mut MqttReceiver) {
+        let client_id = receiver.client().client_id();
+        while let Some(msg) = receiver.try_poll() {
+            self.forward_message(&msg, &client_id).await;
+        }
+    }
+
+    /// Writes the buffer of the client, then clears the journal, e.g. before
+    /// stopping. Fails if the database is still unavailable, leaving the
+    /// messages to recover at the next start.
+    pub async fn flush(&mut self) -> Result<(), DbClientError> {
+        self.resume().await;
+        self.db.drain_buffer().await?;
+        self.checkpoint().await
+    }
+
+    /// Messages in the journal, written or not.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    async fn forward_message(&mut self, msg: &paho_mqtt::Message, client_id: &str) {
+        let info = MessageInfo::from(msg).with_client_id(client_id);
+        if let Err(e) = self.forward(&info, msg.payload()).await {
+            log::error!("Failed to forward message on '{}': {}", msg.topic(), e);
+        }
+    }
+
+    /// Pushes the message whose push was interrupted, if any.
+    async fn resume(&mut self) {
+        let Some(letter) = &self.pushing else {
+            return;
+        };
+        if let Err(e) = self
+            .db
+            .push_with_info(&letter.info(), &letter.payload)
+            .await
+        {
+            log::error!("Failed to forward message on '{}': {}", letter.topic, e);
+        }
+        self.pushing = None;
+        self.pushed += 1;
+    }
+
+    fn append(&mut self, letter: &DeadLetter) -> Result<(), DbClientError> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.journal)
+                    .map_err(DbClientError::Journal)?,
+            ),
+        };
+        writeln!(file, "{}", letter.to_json())
+            .and_then(|()| file.sync_data())
+            .map_err(DbClientError::Journal)?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Removes the entries of the pushed messages from the journal, once
+    /// none of them is left in the buffer of the client, whether written or
+    /// dead lettered.
+    ///
+    /// The journal is truncated in place without syncing it, as a crash
+    /// before the truncation is persisted only writes the messages again.
+    async fn checkpoint(&mut self) -> Result<(), DbClientError> {
+        if self.pushed == 0 || self.db.buffered().await > 0 {
+            return Ok(());
+        }
+        let left = self.pending - self.pushed;
+        if left == 0 {
+            match &self.file {
+                Some(file) => file.set_len(0),
+                None => dead_letter::rewrite_file(&self.journal, &[]),
+            }
+            .map_err(DbClientError::Journal)?;
+        } else {
+            // The entry of an interrupted push, the last one, is kept.
+            let lines = dead_letter::read_file(&self.journal).map_err(DbClientError::Journal)?;
+            dead_letter::rewrite_file(&self.journal, &lines[lines.len().saturating_sub(left)..])
+                .map_err(DbClientError::Journal)?;
+            // Reopened, as the file was replaced.
+            self.file = None;
+        }
+        self.pending = left;
+        self.pushed = 0;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use super::*;
+    use crate::SqliteClient;
+
+    async fn client(path: &std::path::Path) -> SqliteClient {
+        let config: crate::setup_config::SqlServerSetupConfig =
+            serde_json::from_value(serde_json::json!({
+                "driver": "sqlite",
+                "database": path,
+                "topic_table_map": {
+                    "readings": {
+                        "table": "readings",
+                        "fields": {"id": "id", "temp": "temp"},
+                        "write": {"deduplicate": {"keys": ["id"]}},
+                    },
+                },
+            }))
+            .unwrap();
+        SqliteClient::start(config.into()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn recovers_journaled_messages_once() {
+        let dir = std::env::temp_dir();
+        let db_path = dir.join(format!("delivery-{}.db", std::process::id()));
+        let journal = dir.join(format!("delivery-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let _ = std::fs::remove_file(&journal);
+
+        let db = client(&db_path).await;
+        db.query(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, temp REAL)",
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        // A message written before a crash that left it in the journal.
+        let mut forwarder = Forwarder::new(&db, &journal);
+        let info = MessageInfo::new("readings");
+        forwarder
+            .forward(&info, br#"{"id": 1, "temp": 20.5}"#)
+            .await
+            .unwrap();
+        assert_eq!(forwarder.pending(), 1);
+        forwarder.flush().await.unwrap();
+        assert_eq!(forwarder.pending(), 0);
+        assert!(dead_letter::read_file(&journal).unwrap().is_empty());
+        forwarder
+            .append(&DeadLetter::new(&info, br#"{"id": 1, "temp": 20.5}"#, ""))
+            .unwrap();
+        forwarder
+            .append(&DeadLetter::new(&info, br#"{"id": 2, "temp": 21.0}"#, ""))
+            .unwrap();
+
+        let mut forwarder = Forwarder::new(&db, &journal);
+        let report = forwarder.recover().await.unwrap();
+        assert_eq!(
+            report,
+            ReplayReport {
+                replayed: 2,
+                failed: 0
+            }
+        );
+        assert_eq!(forwarder.pending(), 0);
+        assert!(dead_letter::read_file(&journal).unwrap().is_empty());
+
+        let rows = db
+            .query("SELECT id, temp FROM readings ORDER BY id", vec![])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                serde_json::json!({"id": 1, "temp": 20.5}),
+                serde_json::json!({"id": 2, "temp": 21.0}),
+            ]
+        );
+
+        std::fs::remove_file(db_path).unwrap();
+        std::fs::remove_file(journal).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pushes_interrupted_messages_again() {
+        use futures_util::FutureExt;
+
+        let dir = std::env::temp_dir();
+        let db_path = dir.join(format!("delivery-int-{}.db", std::process::id()));
+        let journal = dir.join(format!("delivery-int-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let _ = std::fs::remove_file(&journal);
+
+        let db = client(&db_path).await;
+        db.query(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, temp REAL)",
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        let mut forwarder = Forwarder::new(&db, &journal);
+        let info = MessageInfo::new("readings");
+        forwarder
+            .forward(&info, br#"{"id": 1, "temp": 20.5}"#)
+            .await
+            .unwrap();
+        // Dropped once journaled, before the push completes.
+        assert!(forwarder
+            .forward(&info, br#"{"id": 2, "temp": 21.0}"#)
+            .now_or_never()
+            .is_none());
+        assert_eq!(forwarder.pending(), 2);
+
+        forwarder.checkpoint().await.unwrap();
+        let lines = dead_letter::read_file(&journal).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#"\"id\": 2"#));
+
+        forwarder.flush().await.unwrap();
+        assert_eq!(forwarder.pending(), 0);
+        assert!(dead_letter::read_file(&journal).unwrap().is_empty());
+        let rows = db
+            .query("SELECT id FROM readings ORDER BY id", vec![])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            [serde_json::json!({"id": 1}), serde_json::json!({"id": 2})]
+        );
+
+        std::fs::remove_file(db_path).unwrap();
+        std::fs::remove_file(journal).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_messages_the_journal_cannot_record() {
+        let dir = std::env::temp_dir();
+        let db_path = dir.join(format!("delivery-nj-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+
+        let db = client(&db_path).await;
+        db.query(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, temp REAL)",
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        let journal = dir.join("delivery-missing").join("journal.jsonl");
+        let mut forwarder = Forwarder::new(&db, journal);
+        assert!(matches!(
+            forwarder
+                .forward(&MessageInfo::new("readings"), br#"{"id": 1, "temp": 20.5}"#)
+                .await,
+            Err(DbClientError::Journal(_))
+        ));
+        assert_eq!(forwarder.pending(), 0);
+
+        let rows = db.query("SELECT id FROM readings", vec![]).await.unwrap();
+        assert_eq!(rows, [serde_json::json!({"id": 1})]);
+
+        std::fs::remove_file(db_path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+/// SQL syntax of a database, as far as the generated statements differ.
+#[derive(Debug, Clone, Copy)]
+pub struct Dialect {
+    /// Character quoting identifiers.
+    pub quote: char,
+    pub upsert: Upsert,
+    /// Start of a statement replacing the conflicting rows, if supported.
+    pub replace: Option<&'static str>,
+    /// Whether a single `ALTER TABLE` can add several columns.
+    pub multiple_add_column: bool,
+    /// Most parameters a single statement can bind.
+    pub max_placeholders: usize,
+    /// Whether column names are case insensitive, as in MySQL and SQLite,
+    /// unlike the quoted identifiers of PostgreSQL.
+    pub fold_column_case: bool,
+    pub types: TypeNames,
+}
+
+/// Clause updating the row a new row conflicts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upsert {
+    /// `ON DUPLICATE KEY UPDATE c = VALUES(c)`, on any unique key.
+    OnDuplicateKey,
+    /// `ON CONFLICT (k) DO UPDATE SET c = EXCLUDED.c`, on the given key.
+    OnConflict,
+}
+
+/// Column types created for each kind of value.
+#[derive(Debug, Clone, Copy)]
+pub struct TypeNames {
+    /// Auto-incremented primary key.
+    pub id: &'static str,
+    pub boolean: &'static str,
+    pub integer: &'static str,
+    pub unsigned: &'static str,
+    pub float: &'static str,
+    pub decimal: &'static str,
+    pub text: &'static str,
+    pub json: &'static str,
+    pub datetime: &'static str,
+}
+
+impl Dialect {
+    #[cfg(feature = "mysql")]
+    pub const MYSQL: Dialect = Dialect {
+        quote: '`',
+        upsert: Upsert::OnDuplicateKey,
+        replace: Some("REPLACE INTO"),
+        multiple_add_column: true,
+        max_placeholders: u16::MAX as usize,
+        fold_column_case: true,
+        types: TypeNames {
+            id: "BIGINT AUTO_INCREMENT PRIMARY KEY",
+            boolean: "BOOLEAN",
+            integer: "BIGINT",
+            unsigned: "BIGINT UNSIGNED",
+            float: "DOUBLE",
+            decimal: "DECIMAL(65, 30)",
+            text: "TEXT",
+            json: "JSON",
+            datetime: "DATETIME(6)",
+        },
+    };
+
+    #[cfg(feature = "postgres")]
+    pub const POSTGRES: Dialect = Dialect {
+        quote: '"',
+        upsert: Upsert::OnConflict,
+        replace: None,
+        multiple_add_column: true,
+        max_placeholders: u16::MAX as usize,
+        fold_column_case: false,
+        types: TypeNames {
+            id: "BIGSERIAL PRIMARY KEY",
+            boolean: "BOOLEAN",
+            integer: "BIGINT",
+            unsigned: "NUMERIC(20)",
+            float: "DOUBLE PRECISION",
+            decimal: "NUMERIC",
+            text: "TEXT",
+            json: "JSONB",
+            datetime: "TIMESTAMP(6)",
+        },
+    };
+
+    #[cfg(feature = "sqlite")]
+    pub const SQLITE: Dialect = Dialect {
+        quote: '"',
+        upsert: Upsert::OnConflict,
+        replace: Some("REPLACE INTO"),
+        multiple_add_column: false,
+        // SQLITE_MAX_VARIABLE_NUMBER since SQLite 3.32.
+        max_placeholders: 32766,
+        fold_column_case: true,
+        types: TypeNames {
+            id: "INTEGER PRIMARY KEY AUTOINCREMENT",
+            boolean: "BOOLEAN",
+            integer: "INTEGER",
+            unsigned: "UNSIGNED BIG INT",
+            float: "REAL",
+            decimal: "NUMERIC",
+            text: "TEXT",
+            json: "JSON",
+            datetime: "DATETIME",
+        },
+    };
+
+    /// Whether `a` and `b` name the same column.
+    pub fn same_column(&self, a: &str, b: &str) -> bool {
+        match self.fold_column_case {
+            true => a.eq_ignore_ascii_case(b),
+            false => a == b,
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use super::dialect::{Dialect, TypeNames};
+use super::schema::ColumnType;
+use super::{identifier, SqlValue};
+
+/// Column type created for a value, or `None` for `NULL` since nothing can
+/// be inferred from it.
+pub fn infer_type(value: &SqlValue, types: &TypeNames) -> Option<&'static str> {
+    match value {
+        SqlValue::Null => None,
+        SqlValue::Bool(_) => Some(types.boolean),
+        SqlValue::Int(_) => Some(types.integer),
+        SqlValue::UInt(_) => Some(types.unsigned),
+        SqlValue::Float(_) => Some(types.float),
+        SqlValue::Decimal(_) => Some(types.decimal),
+        SqlValue::Text(_) => Some(types.text),
+        SqlValue::Json(_) => Some(types.json),
+        SqlValue::DateTime(_) => Some(types.datetime),
+    }
+}
+
+/// Whether writing `value` into a column of type `column` would lose
+/// information, i.e. the column would need a wider type to hold it.
+pub fn is_narrowing(column: &ColumnType, value: &SqlValue) -> bool {
+    match (column, value) {
+        (ColumnType::Integer { .. }, SqlValue::Float(v)) => v.fract() != 0.0,
+        (ColumnType::Integer { unsigned: false }, SqlValue::UInt(v)) => *v > i64::MAX as u64,
+        (ColumnType::Integer { unsigned: true }, SqlValue::Int(v)) => *v < 0,
+        (
+            ColumnType::Integer { .. } | ColumnType::Float | ColumnType::Decimal,
+            SqlValue::Json(_),
+        ) => true,
+        (ColumnType::DateTime | ColumnType::Boolean, SqlValue::Json(_)) => true,
+        (ColumnType::Boolean, SqlValue::Int(v)) => !matches!(v, 0 | 1),
+        (ColumnType::Boolean, SqlValue::UInt(v)) => !matches!(v, 0 | 1),
+        _ => false,
+    }
+}
+
+/// `CREATE TABLE` statement for the given columns and their types.
+pub fn create_table(table: &str, columns: &[(String, &str)], dialect: &Dialect) -> Option<String> {
+    let definitions = column_definitions(columns, "", dialect.quote)?;
+    Some(format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        identifier::quote(table, dialect.quote)?,
+        definitions.join(", ")
+    ))
+}
+
+/// `ALTER TABLE` statements adding the given columns, a single one unless
+/// the database adds one column per statement.
+pub fn add_columns(
+    table: &str,
+    columns: &[(String, &str)],
+    dialect: &Dialect,
+) -> Option<Vec<String>> {
+    let table = identifier::quote(table, dialect.quote)?;
+    let definitions = column_definitions(columns, "ADD COLUMN ", dialect.quote)?;
+
+    if dialect.multiple_add_column {
+        return Some(vec![format!(
+            "ALTER TABLE {} {}",
+            table,
+            definitions.join(", ")
+        )]);
+    }
+    Some(
+        definitions
+            .into_iter()
+            .map(|d| format!("ALTER TABLE {} {}", table, d))
+            .collect(),
+    )
+}
+
+fn column_definitions(
+    columns: &[(String, &str)],
+    prefix: &str,
+    quote: char,
+) -> Option<Vec<String>> {
+    let mut out = Vec::with_capacity(columns.len());
+    for (name, ty) in columns {
+        out.push(format!(
+            "{}{} {} NULL",
+            prefix,
+            identifier::quote(name, quote)?,
+            ty
+        ));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn builds_ddl() {
+        let mysql = Dialect::MYSQL;
+        let columns = [
+            (
+                "temp".to_string(),
+                infer_type(&SqlValue::Float(1.5), &mysql.types).unwrap(),
+            ),
+            (
+                "on".to_string(),
+                infer_type(&SqlValue::Bool(true), &mysql.types).unwrap(),
+            ),
+        ];
+
+        assert_eq!(
+            create_table("t", &columns, &mysql).unwrap(),
+            "CREATE TABLE IF NOT EXISTS `t` (`temp` DOUBLE NULL, `on` BOOLEAN NULL)"
+        );
+        assert_eq!(
+            add_columns("t", &columns, &mysql).unwrap(),
+            ["ALTER TABLE `t` ADD COLUMN `temp` DOUBLE NULL, ADD COLUMN `on` BOOLEAN NULL"]
+        );
+        assert!(create_table("", &columns, &mysql).is_none());
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn builds_postgres_ddl() {
+        let postgres = Dialect::POSTGRES;
+        let columns = [(
+            "temp".to_string(),
+            infer_type(&SqlValue::Float(1.5), &postgres.types).unwrap(),
+        )];
+
+        assert_eq!(
+            create_table("t", &columns, &postgres).unwrap(),
+            "CREATE TABLE IF NOT EXISTS \"t\" (\"temp\" DOUBLE PRECISION NULL)"
+        );
+    }
+
+    #[test]
+    fn detects_narrowing() {
+        let int = ColumnType::Integer { unsigned: false };
+        assert!(is_narrowing(&int, &SqlValue::Float(23.7)));
+        assert!(!is_narrowing(&int, &SqlValue::Float(23.0)));
+        assert!(is_narrowing(&int, &SqlValue::UInt(u64::MAX)));
+        assert!(!is_narrowing(&ColumnType::Float, &SqlValue::Int(3)));
+        assert!(!is_narrowing(&ColumnType::Text, &SqlValue::Float(23.7)));
+    }
+}
@@ -0,0 +1,68 @@
+/// Longest table or column name accepted by MySQL, and by PostgreSQL within
+/// a character.
+const MAX_LEN: usize = 64;
+
+/// Checks `name` against the rules of MySQL quoted identifiers, the
+/// strictest of the supported databases: not empty, at most 64 characters,
+/// no NUL, no supplementary characters and no trailing space.
+pub fn validate(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAX_LEN
+        && !name.ends_with(' ')
+        && name.chars().all(|c| c != '\0' && (c as u32) <= 0xFFFF)
+}
+
+/// Quotes `name` with `quote` to be used as an identifier, or `None` if it
+/// is not valid.
+pub fn quote(name: &str, quote: char) -> Option<String> {
+    if !validate(name) {
+        return None;
+    }
+    let escaped = name.replace(quote, &format!("{0}{0}", quote));
+    Some(format!("{0}{1}{0}", quote, escaped))
+}
+
+/// Quotes every name of `names` and joins them into a column list.
+pub fn quote_list<'a>(
+    names: impl IntoIterator<Item = &'a String>,
+    quote: char,
+) -> Result<String, String> {
+    let mut out = String::new();
+    for name in names {
+        if !out.is_empty() {
+            out.push_str(", ");
+        }
+        out.push_str(&self::quote(name, quote).ok_or_else(|| name.clone())?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quotes_identifiers() {
+        assert_eq!(quote("temp", '`').unwrap(), "`temp`");
+        assert_eq!(
+            quote("a`; DROP TABLE t; --", '`').unwrap(),
+            "`a``; DROP TABLE t; --`"
+        );
+        assert_eq!(quote("a\"b`", '"').unwrap(), "\"a\"\"b`\"");
+        assert_eq!(
+            quote_list(&["a".to_string(), "b c".to_string()], '`').unwrap(),
+            "`a`, `b c`"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_identifiers() {
+        for name in ["", "trailing ", "nul\0", "emoji\u{1F600}", &"x".repeat(65)] {
+            assert!(quote(name, '`').is_none(), "{:?}", name);
+        }
+        assert_eq!(
+            quote_list(&["a".to_string(), String::new()], '`'),
+            Err(String::new())
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use super::{DbClientError, Row, SqlValue};
+use crate::setup_config::sql_server::{
+    FieldRule, FieldSource, Metadata, NestedValues, TableMapping, UnitConversion,
+};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Properties of a received message besides its payload.
+#[derive(Debug, Clone)]
+pub struct MessageInfo<'a> {
+    pub topic: &'a str,
+    pub qos: i32,
+    pub retained: bool,
+    pub received_at: time::OffsetDateTime,
+    /// MQTT v5 user properties, in the order they were sent.
+    pub user_properties: Vec<(String, String)>,
+    /// Client id of the forwarder that received the message, unknown to
+    /// the message itself so `None` until set with [`Self::with_client_id`].
+    pub client_id: Option<&'a str>,
+}
+
+impl<'a> MessageInfo<'a> {
+    /// Info of a message on `topic` received just now, with QoS 0, not
+    /// retained and without properties.
+    pub fn new(topic: &'a str) -> Self {
+        MessageInfo {
+            topic,
+            qos: 0,
+            retained: false,
+            received_at: time::OffsetDateTime::now_utc(),
+            user_properties: Vec::new(),
+            client_id: None,
+        }
+    }
+
+    pub fn with_client_id(mut self, client_id: &'a str) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
+}
+
+impl<'a> From<&'a paho_mqtt::Message> for MessageInfo<'a> {
+    fn from(msg: &'a paho_mqtt::Message) -> Self {
+        MessageInfo {
+            topic: msg.topic(),
+            qos: msg.qos(),
+            retained: msg.retained(),
+            received_at: time::OffsetDateTime::now_utc(),
+            user_properties: msg
+                .properties()
+                .iter(paho_mqtt::PropertyCode::UserProperty)
+                .filter_map(|p| p.get_string_pair())
+                .collect(),
+            client_id: None,
+        }
+    }
+}
+
+/// Builds the row written for `payload` according to `mapping`.
+pub fn apply(
+    mapping: &TableMapping,
+    payload: Value,
+    info: &MessageInfo,
+    nested: &NestedValues,
+) -> Result<Row, DbClientError> {
+    let nested_error = |column| DbClientError::NestedValue {
+        topic: info.topic.to_string(),
+        column,
+    };
+
+    let mut row = if mapping.fields.is_empty() {
+        let Value::Object(data) = payload else {
+            return Err(DbClientError::Unsupported);
+        };
+        Row::from_object(data, nested).map_err(nested_error)?
+    } else {
+        map_fields(mapping, payload, info, nested)?
+    };
+
+    for (column, meta) in mapping.metadata.columns() {
+        let value = match metadata_value(&meta, info) {
+            Field::Sql(v) => v,
+            Field::Json(Value::Object(o)) => SqlValue::Json(Value::Object(o)),
+            Field::Json(v) => Row::scalar(v),
+        };
+        row.set(column, value);
+    }
+
+    Ok(row)
+}
+
+fn map_fields(
+    mapping: &TableMapping,
+    payload: Value,
+    info: &MessageInfo,
+    nested: &NestedValues,
+) -> Result<Row, DbClientError> {
+    let nested_error = |column| DbClientError::NestedValue {
+        topic: info.topic.to_string(),
+        column,
+    };
+
+    let mut row = Row::default();
+    let mut used_keys = HashSet::new();
+    for (column, rule) in &mapping.fields {
+        if let Some(key) = source_key(&rule.source) {
+            used_keys.insert(key);
+        }
+
+        let value =
+            field_value(rule, &payload, info).map_err(|reason| DbClientError::FieldMapping {
+                topic: info.topic.to_string(),
+                column: column.clone(),
+                reason,
+            })?;
+        match value {
+            Field::Json(v) => row.push(column.clone(), v, nested).map_err(nested_error)?,
+            Field::Sql(v) => {
+                row.columns.push(column.clone());
+                row.values.push(v);
+            }
+        }
+    }
+
+    if mapping.include_unmapped {
+        if let Value::Object(data) = payload {
+            for (k, v) in data {
+                if !used_keys.contains(k.as_str()) && !mapping.fields.contains_key(&k) {
+                    row.push(k, v, nested).map_err(nested_error)?;
+                }
+            }
+        }
+    }
+
+    Ok(row)
+}
+
+enum Field {
+    Json(Value),
+    Sql(SqlValue),
+}
+
+/// The top level payload key a source reads from, if any.
+fn source_key(source: &FieldSource) -> Option<String> {
+    match source {
+        FieldSource::Key(k) => Some(k.clone()),
+        FieldSource::Pointer(p) => p
+            .split('/')
+            .nth(1)
+            .map(|k| k.replace("~1", "/").replace("~0", "~")),
+        FieldSource::Value(_) | FieldSource::Meta(_) => None,
+    }
+}
+
+/// Reads the value of a field and applies its transforms, in order: enum
+/// mapping, unit conversion, scale and offset.
+///
+/// A source missing from the payload gives `NULL`.
+fn field_value(rule: &FieldRule, payload: &Value, info: &MessageInfo) -> Result<Field, String> {
+    let value = match &rule.source {
+        FieldSource::Key(k) => payload.get(k).cloned(),
+        FieldSource::Pointer(p) => payload.pointer(p).cloned(),
+        FieldSource::Value(v) => Some(v.clone()),
+        FieldSource::Meta(meta) => match metadata_value(meta, info) {
+            Field::Json(v) => Some(v),
+            sql => return Ok(sql),
+        },
+    };
+
+    let Some(mut value) = value.filter(|v| !v.is_null()) else {
+        return Ok(Field::Json(Value::Null));
+    };
+
+    if let Some(map) = &rule.map {
+        let key = match &value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        value = map
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| format!("'{}' has no enum value", key))?;
+    }
+
+    if rule.unit.is_none() && rule.scale.is_none() && rule.offset.is_none() {
+        return Ok(Field::Json(value));
+    }
+
+    let mut number = value
+        .as_f64()
+        .ok_or_else(|| format!("{} is not a number", value))?;
+    if let Some(unit) = &rule.unit {
+        number = convert_unit(number, unit)?;
+    }
+    if let Some(scale) = rule.scale {
+        number *= scale;
+    }
+    if let Some(offset) = rule.offset {
+        number += offset;
+    }
+    Ok(Field::Sql(SqlValue::Float(number)))
+}
+
+fn metadata_value(meta: &Metadata, info: &MessageInfo) -> Field {
+    Field::Json(match meta {
+        Metadata::Topic => Value::from(info.topic),
+        Metadata::TopicSegment(i) => match info.topic.split('/').nth(*i) {
+            Some(segment) => Value::from(segment),
+            None => Value::Null,
+        },
+        Metadata::ReceivedAt => {
+            let t = info.received_at.to_offset(time::UtcOffset::UTC);
+            return Field::Sql(SqlValue::DateTime(time::PrimitiveDateTime::new(
+                t.date(),
+                t.time(),
+            )));
+        }
+        Metadata::Qos => Value::from(info.qos),
+        Metadata::Retained => Value::from(info.retained),
+        Metadata::UserProperties => Value::Object(
+            info.user_properties
+                .iter()
+                .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+                .collect(),
+        ),
+        Metadata::UserProperty(name) => info
+            .user_properties
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| Value::from(v.as_str()))
+            .unwrap_or_default(),
+        Metadata::ClientId => info.client_id.map(Value::from).unwrap_or_default(),
+    })
+}
+
+/// Factor and offset from a unit to the base unit of its dimension, such
+/// that `base = value * factor + offset`.
+///
+/// Symbols are case sensitive, as `mW` and `MW` differ by 10^9, while the
+/// names of temperature units are not.
+fn unit_to_base(unit: &str) -> Option<(&'static str, f64, f64)> {
+    Some(match unit {
+        "K" => ("temperature", 1.0, 0.0),
+        "C" | "°C" => ("temperature", 1.0, 273.15),
+        "F" | "°F" => ("temperature", 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+        "Pa" => ("pressure", 1.0, 0.0),
+        "hPa" | "mbar" => ("pressure", 100.0, 0.0),
+        "kPa" => ("pressure", 1e3, 0.0),
+        "MPa" => ("pressure", 1e6, 0.0),
+        "bar" => ("pressure", 1e5, 0.0),
+        "psi" => ("pressure", 6_894.757_293_168, 0.0),
+        "mm" => ("length", 1e-3, 0.0),
+        "cm" => ("length", 1e-2, 0.0),
+        "m" => ("length", 1.0, 0.0),
+        "km" => ("length", 1e3, 0.0),
+        "in" => ("length", 0.0254, 0.0),
+        "ft" => ("length", 0.3048, 0.0),
+        "ms" => ("time", 1e-3, 0.0),
+        "s" => ("time", 1.0, 0.0),
+        "min" => ("time", 60.0, 0.0),
+        "h" => ("time", 3600.0, 0.0),
+        "mW" => ("power", 1e-3, 0.0),
+        "W" => ("power", 1.0, 0.0),
+        "kW" => ("power", 1e3, 0.0),
+        "MW" => ("power", 1e6, 0.0),
+        "J" => ("energy", 1.0, 0.0),
+        "Wh" => ("energy", 3600.0, 0.0),
+        "kWh" => ("energy", 3.6e6, 0.0),
+        "MWh" => ("energy", 3.6e9, 0.0),
+        name => match name.to_ascii_lowercase().as_str() {
+            "kelvin" => ("temperature", 1.0, 0.0),
+            "celsius" => ("temperature", 1.0, 273.15),
+            "fahrenheit" => ("temperature", 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+            _ => return None,
+        },
+    })
+}
+
+fn convert_unit(value: f64, conversion: &UnitConversion) -> Result<f64, String> {
+    let unknown = |unit: &str| format!("unknown unit '{}'", unit);
+    let (from_dim, from_factor, from_offset) =
+        unit_to_base(&conversion.from).ok_or_else(|| unknown(&conversion.from))?;
+    let (to_dim, to_factor, to_offset) =
+        unit_to_base(&conversion.to).ok_or_else(|| unknown(&conversion.to))?;
+
+    if from_dim != to_dim {
+        return Err(format!(
+            "cannot convert {} '{}' to {} '{}'",
+            from_dim, conversion.from, to_dim, conversion.to
+        ));
+    }
+
+    let base = value * from_factor + from_offset;
+    Ok((base - to_offset) / to_factor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn mapping(value: Value) -> TableMapping {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn deserializes_bare_table_names() {
+        let m = mapping(json!("runtime"));
+        assert_eq!(m, TableMapping::from("runtime"));
+        assert!(m.include_unmapped);
+    }
+
+    #[test]
+    fn applies_field_rules() {
+        let m = mapping(json!({
+            "table": "runtime",
+            "fields": {
+                "temp_c": {"pointer": "/sensors/temp", "unit": {"from": "F", "to": "C"}},
+                "power_w": {"key": "power", "scale": 1000.0},
+                "state": {"key": "state", "enum": {"ON": 1, "OFF": 0}},
+                "site": {"value": "north"},
+                "device": {"meta": {"topic_segment": 3}},
+                "qos": {"meta": "qos"},
+                "serial": "sn",
+            }
+        }));
+        let payload = json!({
+            "sensors": {"temp": 212.0},
+            "power": 1.5,
+            "state": "ON",
+            "sn": "A1",
+            "ignored": true,
+        });
+        let mut info = MessageInfo::new("data/REP240003/runtime/7121339009090197001");
+        info.qos = 1;
+
+        let row = apply(&m, payload, &info, &NestedValues::Reject).unwrap();
+        assert_eq!(
+            row.columns,
+            ["device", "power_w", "qos", "serial", "site", "state", "temp_c"]
+        );
+        assert_eq!(
+            row.values,
+            [
+                SqlValue::Text("7121339009090197001".to_string()),
+                SqlValue::Float(1500.0),
+                SqlValue::Int(1),
+                SqlValue::Text("A1".to_string()),
+                SqlValue::Text("north".to_string()),
+                SqlValue::Int(1),
+                SqlValue::Float(100.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_unmapped_keys_on_request() {
+        let m = mapping(json!({
+            "table": "runtime",
+            "fields": {"temperature": "temp", "nested": "/a/b"},
+            "include_unmapped": true,
+        }));
+        let payload = json!({"temp": 20, "a": {"b": 1}, "humidity": 40});
+
+        let row = apply(&m, payload, &MessageInfo::new("t"), &NestedValues::Reject).unwrap();
+        assert_eq!(row.columns, ["nested", "temperature", "humidity"]);
+    }
+
+    #[test]
+    fn adds_metadata_columns() {
+        let m = mapping(json!({
+            "table": "runtime",
+            "metadata": {
+                "received_at": "received_at",
+                "topic": "topic",
+                "topic_segments": {"1": "site"},
+                "user_properties": "properties",
+                "client_id": "forwarder",
+            },
+        }));
+        let mut info = MessageInfo::new("data/REP240003/runtime").with_client_id("saltyfishie_1");
+        info.user_properties = vec![("unit".to_string(), "C".to_string())];
+
+        let row = apply(
+            &m,
+            json!({"temp": 20, "topic": "spoofed"}),
+            &info,
+            &NestedValues::Reject,
+        )
+        .unwrap();
+        assert_eq!(
+            row.columns,
+            [
+                "temp",
+                "topic",
+                "received_at",
+                "site",
+                "properties",
+                "forwarder"
+            ]
+        );
+        assert_eq!(
+            &row.values[1..],
+            [
+                SqlValue::Text("data/REP240003/runtime".to_string()),
+                SqlValue::DateTime(time::PrimitiveDateTime::new(
+                    info.received_at.date(),
+                    info.received_at.time()
+                )),
+                SqlValue::Text("REP240003".to_string()),
+                SqlValue::Json(json!({"unit": "C"})),
+                SqlValue::Text("saltyfishie_1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_field_errors() {
+        let m = mapping(json!({
+            "table": "runtime",
+            "fields": {"state": {"key": "state", "enum": {"ON": 1}}},
+        }));
+        let err = apply(
+            &m,
+            json!({"state": "BROKEN"}),
+            &MessageInfo::new("t"),
+            &NestedValues::Reject,
+        )
+        .unwrap_err();
+        assert!(matches!(err, DbClientError::FieldMapping { column, .. } if column == "state"));
+
+        let wrong_dimension = UnitConversion {
+            from: "kWh".to_string(),
+            to: "bar".to_string(),
+        };
+        assert!(convert_unit(1.0, &wrong_dimension).is_err());
+    }
+
+    #[test]
+    fn matches_unit_symbols_by_case() {
+        let convert = |value, from: &str, to: &str| {
+            convert_unit(
+                value,
+                &UnitConversion {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                },
+            )
+        };
+        assert_eq!(convert(1500.0, "mW", "W").unwrap(), 1.5);
+        assert_eq!(convert(1.5, "MW", "kW").unwrap(), 1500.0);
+        assert_eq!(convert(0.0, "Celsius", "K").unwrap(), 273.15);
+        assert!(convert(1.0, "mw", "W").is_err());
+    }
+}
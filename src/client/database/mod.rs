@@ -0,0 +1,570 @@
+mod backend;
+mod batch;
+mod client;
+mod coerce;
+mod dead_letter;
+mod delivery;
+mod dialect;
+mod evolve;
+mod identifier;
+mod mapping;
+mod publisher;
+mod query;
+mod retry;
+mod row;
+mod schema;
+mod write;
+
+use super::setup_config;
+pub use backend::{Backend, ConnectOptions};
+pub use batch::{BatchConfig, BatchReport, BatchWriter, FailedRow};
+pub use client::{SqlClient, SqlClientConfig};
+pub use dead_letter::{DeadLetter, ReplayReport};
+pub use delivery::Forwarder;
+use futures_util::future::BoxFuture;
+pub use mapping::MessageInfo;
+pub use publisher::TablePublisher;
+pub use query::{Order, Page, TableQuery};
+pub use row::{Row, SqlValue};
+pub use schema::{ColumnType, TableSchema};
+
+#[cfg(feature = "mysql")]
+pub type MysqlClient = SqlClient<sqlx::MySql>;
+#[cfg(feature = "mysql")]
+pub type MysqlClientConfig = SqlClientConfig<sqlx::MySql>;
+#[cfg(feature = "mysql")]
+pub type MysqlBatchWriter<'a> = BatchWriter<'a, sqlx::MySql>;
+
+#[cfg(feature = "postgres")]
+pub type PostgresClient = SqlClient<sqlx::Postgres>;
+#[cfg(feature = "postgres")]
+pub type PostgresClientConfig = SqlClientConfig<sqlx::Postgres>;
+#[cfg(feature = "postgres")]
+pub type PostgresBatchWriter<'a> = BatchWriter<'a, sqlx::Postgres>;
+
+#[cfg(feature = "sqlite")]
+pub type SqliteClient = SqlClient<sqlx::Sqlite>;
+#[cfg(feature = "sqlite")]
+pub type SqliteClientConfig = SqlClientConfig<sqlx::Sqlite>;
+#[cfg(feature = "sqlite")]
+pub type SqliteBatchWriter<'a> = BatchWriter<'a, sqlx::Sqlite>;
+
+#[derive(Debug)]
+pub enum DbClientError {
+    MqttPayload(serde_json::Error),
+    Unsupported,
+    /// The connection pool could not be established.
+    Connection(sqlx::Error),
+    /// A statement against `table` failed, `topic` is the message being
+    /// pushed if any.
+    Query {
+        topic: Option<String>,
+        table: String,
+        source: sqlx::Error,
+    },
+    /// No table is mapped to the topic of the pushed message.
+    UnknownMapping {
+        topic: String,
+    },
+    /// A query passed to [`SqlClient::query`] failed, or its rows could not
+    /// be decoded.
+    Fetch(sqlx::Error),
+    /// The dead letter file could not be read or written.
+    DeadLetterFile(std::io::Error),
+    /// The dead letter could not be republished.
+    DeadLetterPublish(paho_mqtt::Error),
+    /// The buffer is full while the database is unavailable.
+    BufferFull,
+    /// The buffer spool file could not be read or written.
+    Spool(std::io::Error),
+    /// The delivery journal could not be read or written.
+    Journal(std::io::Error),
+    /// The publisher watermarks could not be read or written.
+    Watermark(std::io::Error),
+    /// A row of `table` could not be published.
+    Publish {
+        table: String,
+        source: paho_mqtt::Error,
+    },
+    /// The topic template of `table` names a column its rows lack.
+    TopicTemplate {
+        table: String,
+        column: String,
+    },
+    /// A time range was queried on `table` without a time column.
+    MissingTimeColumn {
+        table: String,
+    },
+    /// A table or column name is not a valid identifier.
+    InvalidIdentifier {
+        topic: Option<String>,
+        identifier: String,
+    },
+    /// The payload holds a key that is not a column of `table`, which the
+    /// configured [`setup_config::sql_server::UnknownColumns`] rejects.
+    UnknownColumn {
+        topic: String,
+        table: String,
+        column: String,
+    },
+    /// The value of `column` cannot be converted to the column type.
+    Coercion {
+        topic: String,
+        table: String,
+        column: String,
+        reason: String,
+    },
+    /// The value of `column` would not fit the existing column type, and
+    /// schema evolution never changes existing columns.
+    NarrowingChange {
+        topic: String,
+        table: String,
+        column: String,
+    },
+    /// A field rule of the mapping could not produce the value of `column`.
+    FieldMapping {
+        topic: String,
+        column: String,
+        reason: String,
+    },
+    /// The payload holds a nested object or array under `column`, which the
+    /// configured [`setup_config::sql_server::NestedValues`] rejects.
+    NestedValue {
+        topic: String,
+        column: String,
+    },
+    /// The row lacks `column`, a key column of the upsert into `table`.
+    MissingKey {
+        topic: String,
+        table: String,
+        column: String,
+    },
+    /// The configured driver was not enabled when building the crate.
+    DriverDisabled(setup_config::sql_server::Driver),
+    /// A value could not be converted to or from its column type.
+    TypeConversion {
+        topic: Option<String>,
+        table: String,
+        source: sqlx::Error,
+    },
+    /// A statement against `table` failed for a reason that may not occur
+    /// again, such as a lost connection or a deadlock.
+    Transient {
+        topic: Option<String>,
+        table: String,
+        source: sqlx::Error,
+    },
+}
+
+impl DbClientError {
+    fn from_query<B: Backend>(topic: Option<&str>, table: &str, source: sqlx::Error) -> Self {
+        let topic = topic.map(str::to_string);
+        let table = table.to_string();
+
+        if is_type_conversion::<B>(&source) {
+            DbClientError::TypeConversion {
+                topic,
+                table,
+                source,
+            }
+        } else if is_transient::<B>(&source) {
+            DbClientError::Transient {
+                topic,
+                table,
+                source,
+            }
+        } else {
+            DbClientError::Query {
+                topic,
+                table,
+                source,
+            }
+        }
+    }
+}
+
+impl DbClientError {
+    /// Whether the write may succeed when retried, as once the database is
+    /// reachable again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DbClientError::Transient { .. } => true,
+            DbClientError::Connection(e) => is_unavailable(e),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the database could not be reached, by any backend, including
+/// through a pool closed while the client shuts down.
+fn is_unavailable(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::Io(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
+
+/// Whether the error may not occur again, either as the database could not
+/// be reached or as reported by the server.
+fn is_transient<B: Backend>(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(err) => B::is_transient(&**err),
+        e => is_unavailable(e),
+    }
+}
+
+/// Whether the error comes from a value not fitting its column, either
+/// while decoding a row or as reported by the server.
+fn is_type_conversion<B: Backend>(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Decode(_) | sqlx::Error::ColumnDecode { .. } => true,
+        sqlx::Error::Database(err) => B::is_type_conversion(&**err),
+        _ => false,
+    }
+}
+
+impl std::fmt::Display for DbClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbClientError::MqttPayload(e) => write!(f, "{}", e),
+            DbClientError::Unsupported => write!(f, "unsupported"),
+            DbClientError::Connection(e) => write!(f, "connection error: {}", e),
+            DbClientError::Query {
+                topic: Some(topic),
+                table,
+                source,
+            } => write!(f, "query on '{}' for '{}' failed: {}", table, topic, source),
+            DbClientError::Query {
+                topic: None,
+                table,
+                source,
+            } => write!(f, "query on '{}' failed: {}", table, source),
+            DbClientError::Fetch(e) => write!(f, "query failed: {}", e),
+            DbClientError::BufferFull => write!(f, "buffer full while the database is unavailable"),
+            DbClientError::Spool(e) => write!(f, "spool error: {}", e),
+            DbClientError::Journal(e) => write!(f, "journal error: {}", e),
+            DbClientError::Watermark(e) => write!(f, "watermark error: {}", e),
+            DbClientError::Publish { table, source } => {
+                write!(f, "failed to publish a row of '{}': {}", table, source)
+            }
+            DbClientError::TopicTemplate { table, column } => write!(
+                f,
+                "topic template of '{}' names '{}', which the row lacks",
+                table, column
+            ),
+            DbClientError::DeadLetterFile(e) => write!(f, "dead letter file error: {}", e),
+            DbClientError::DeadLetterPublish(e) => write!(f, "dead letter publish error: {}", e),
+            DbClientError::MissingTimeColumn { table } => {
+                write!(f, "no time column to query a range of '{}'", table)
+            }
+            DbClientError::UnknownMapping { topic } => {
+                write!(f, "map for '{}' is not specified", topic)
+            }
+            DbClientError::InvalidIdentifier {
+                topic: Some(topic),
+                identifier,
+            } => write!(f, "invalid identifier {:?} for '{}'", identifier, topic),
+            DbClientError::InvalidIdentifier {
+                topic: None,
+                identifier,
+            } => write!(f, "invalid identifier {:?}", identifier),
+            DbClientError::UnknownColumn {
+                topic,
+                table,
+                column,
+            } => write!(
+                f,
+                "unknown column '{}' of '{}' for '{}'",
+                column, table, topic
+            ),
+            DbClientError::Coercion {
+                topic,
+                table,
+                column,
+                reason,
+            } => write!(
+                f,
+                "cannot convert '{}' of '{}' for '{}': {}",
+                column, table, topic, reason
+            ),
+            DbClientError::NarrowingChange {
+                topic,
+                table,
+                column,
+            } => write!(
+                f,
+                "value of '{}' for '{}' does not fit its column in '{}'",
+                column, topic, table
+            ),
+            DbClientError::FieldMapping {
+                topic,
+                column,
+                reason,
+            } => write!(f, "cannot map '{}' for '{}': {}", column, topic, reason),
+            DbClientError::NestedValue { topic, column } => {
+                write!(
+                    f,
+                    "nested value of '{}' for '{}' is rejected",
+                    column, topic
+                )
+            }
+            DbClientError::MissingKey {
+                topic,
+                table,
+                column,
+            } => write!(
+                f,
+                "key column '{}' of '{}' is missing for '{}'",
+                column, table, topic
+            ),
+            DbClientError::DriverDisabled(driver) => write!(
+                f,
+                "driver '{0}' is not enabled, build with the '{0}' feature",
+                driver.name()
+            ),
+            DbClientError::TypeConversion {
+                topic: Some(topic),
+                table,
+                source,
+            } => write!(
+                f,
+                "type conversion on '{}' for '{}' failed: {}",
+                table, topic, source
+            ),
+            DbClientError::TypeConversion {
+                topic: None,
+                table,
+                source,
+            } => write!(f, "type conversion on '{}' failed: {}", table, source),
+            DbClientError::Transient {
+                topic: Some(topic),
+                table,
+                source,
+            } => write!(
+                f,
+                "query on '{}' for '{}' failed, may be retried: {}",
+                table, topic, source
+            ),
+            DbClientError::Transient {
+                topic: None,
+                table,
+                source,
+            } => write!(f, "query on '{}' failed, may be retried: {}", table, source),
+        }
+    }
+}
+
+impl std::error::Error for DbClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbClientError::MqttPayload(e) => Some(e),
+            DbClientError::DeadLetterFile(e)
+            | DbClientError::Spool(e)
+            | DbClientError::Journal(e)
+            | DbClientError::Watermark(e) => Some(e),
+            DbClientError::Publish { source, .. } => Some(source),
+            DbClientError::DeadLetterPublish(e) => Some(e),
+            DbClientError::Connection(e)
+            | DbClientError::Fetch(e)
+            | DbClientError::Query { source: e, .. }
+            | DbClientError::TypeConversion { source: e, .. }
+            | DbClientError::Transient { source: e, .. } => Some(e),
+            DbClientError::Unsupported
+            | DbClientError::BufferFull
+            | DbClientError::TopicTemplate { .. }
+            | DbClientError::MissingTimeColumn { .. }
+            | DbClientError::UnknownMapping { .. }
+            | DbClientError::InvalidIdentifier { .. }
+            | DbClientError::UnknownColumn { .. }
+            | DbClientError::Coercion { .. }
+            | DbClientError::NarrowingChange { .. }
+            | DbClientError::FieldMapping { .. }
+            | DbClientError::NestedValue { .. }
+            | DbClientError::MissingKey { .. }
+            | DbClientError::DriverDisabled(_) => None,
+        }
+    }
+}
+
+/// The operations of a database client, whatever its backend, so the
+/// backend can be chosen by configuration with [`start_client`].
+pub trait DbClient: Send + Sync {
+    fn push<'a>(
+        &'a self,
+        topic: &'a str,
+        payload: &'a str,
+    ) -> BoxFuture<'a, Result<(), DbClientError>>;
+
+    fn push_message<'a>(
+        &'a self,
+        msg: &'a paho_mqtt::Message,
+        client_id: &'a str,
+    ) -> BoxFuture<'a, Result<(), DbClientError>>;
+
+    fn push_with_info<'a>(
+        &'a self,
+        info: &'a MessageInfo<'_>,
+        payload: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), DbClientError>>;
+
+    fn refresh_schema(&self) -> BoxFuture<'_, Result<(), DbClientError>>;
+
+    fn drain_buffer(&self) -> BoxFuture<'_, Result<usize, DbClientError>>;
+
+    fn buffered(&self) -> BoxFuture<'_, usize>;
+
+    fn set_dead_letter_publisher(&self, client: crate::MqttClient);
+
+    fn record_dead_letter<'a>(
+        &'a self,
+        letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), DbClientError>>;
+
+    fn replay_dead_letters(&self) -> BoxFuture<'_, Result<ReplayReport, DbClientError>>;
+
+    fn replay_dead_letter<'a>(
+        &'a self,
+        letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), DbClientError>>;
+
+    fn query<'a>(
+        &'a self,
+        sql: &'a str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>>;
+
+    fn query_table<'a>(
+        &'a self,
+        table: &'a str,
+        query: &'a TableQuery,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>>;
+
+    fn query_topic<'a>(
+        &'a self,
+        topic: &'a str,
+        query: &'a TableQuery,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>>;
+}
+
+impl<B: Backend> DbClient for SqlClient<B> {
+    fn push<'a>(
+        &'a self,
+        topic: &'a str,
+        payload: &'a str,
+    ) -> BoxFuture<'a, Result<(), DbClientError>> {
+        Box::pin(SqlClient::push(self, topic, payload))
+    }
+
+    fn push_message<'a>(
+        &'a self,
+        msg: &'a paho_mqtt::Message,
+        client_id: &'a str,
+    ) -> BoxFuture<'a, Result<(), DbClientError>> {
+        Box::pin(SqlClient::push_message(self, msg, client_id))
+    }
+
+    fn push_with_info<'a>(
+        &'a self,
+        info: &'a MessageInfo<'_>,
+        payload: &'a [u8],
+    ) -> BoxFuture<'a, Result<(), DbClientError>> {
+        Box::pin(SqlClient::push_with_info(self, info, payload))
+    }
+
+    fn refresh_schema(&self) -> BoxFuture<'_, Result<(), DbClientError>> {
+        Box::pin(SqlClient::refresh_schema(self))
+    }
+
+    fn drain_buffer(&self) -> BoxFuture<'_, Result<usize, DbClientError>> {
+        Box::pin(SqlClient::drain_buffer(self))
+    }
+
+    fn buffered(&self) -> BoxFuture<'_, usize> {
+        Box::pin(SqlClient::buffered(self))
+    }
+
+    fn set_dead_letter_publisher(&self, client: crate::MqttClient) {
+        SqlClient::set_dead_letter_publisher(self, client)
+    }
+
+    fn record_dead_letter<'a>(
+        &'a self,
+        letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), DbClientError>> {
+        Box::pin(SqlClient::record_dead_letter(self, letter))
+    }
+
+    fn replay_dead_letters(&self) -> BoxFuture<'_, Result<ReplayReport, DbClientError>> {
+        Box::pin(SqlClient::replay_dead_letters(self))
+    }
+
+    fn replay_dead_letter<'a>(
+        &'a self,
+        letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), DbClientError>> {
+        Box::pin(SqlClient::replay_dead_letter(self, letter))
+    }
+
+    fn query<'a>(
+        &'a self,
+        sql: &'a str,
+        params: Vec<SqlValue>,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>> {
+        Box::pin(SqlClient::query(self, sql, params))
+    }
+
+    fn query_table<'a>(
+        &'a self,
+        table: &'a str,
+        query: &'a TableQuery,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>> {
+        Box::pin(SqlClient::query_table(self, table, query))
+    }
+
+    fn query_topic<'a>(
+        &'a self,
+        topic: &'a str,
+        query: &'a TableQuery,
+    ) -> BoxFuture<'a, Result<Vec<serde_json::Value>, DbClientError>> {
+        Box::pin(SqlClient::query_topic(self, topic, query))
+    }
+}
+
+/// Connects to the database of the configured
+/// [`setup_config::sql_server::Driver`].
+pub async fn start_client(
+    config: setup_config::SqlServerSetupConfig,
+) -> Result<Box<dyn DbClient>, DbClientError> {
+    use setup_config::sql_server::Driver;
+
+    match config.driver {
+        #[cfg(feature = "mysql")]
+        Driver::Mysql => Ok(Box::new(MysqlClient::start(config.into()).await?)),
+        #[cfg(feature = "postgres")]
+        Driver::Postgres => Ok(Box::new(PostgresClient::start(config.into()).await?)),
+        #[cfg(feature = "sqlite")]
+        Driver::Sqlite => Ok(Box::new(SqliteClient::start(config.into()).await?)),
+        #[allow(unreachable_patterns)]
+        driver => Err(DbClientError::DriverDisabled(driver)),
+    }
+}
+
+#[cfg(all(test, feature = "mysql"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classifies_unreachable_databases_as_transient() {
+        let transient = |e| DbClientError::from_query::<sqlx::MySql>(None, "t", e).is_transient();
+        assert!(transient(sqlx::Error::Io(
+            std::io::ErrorKind::ConnectionReset.into()
+        )));
+        assert!(transient(sqlx::Error::PoolTimedOut));
+        assert!(transient(sqlx::Error::PoolClosed));
+        assert!(!transient(sqlx::Error::RowNotFound));
+        assert!(!transient(sqlx::Error::ColumnNotFound("n".into())));
+    }
+}
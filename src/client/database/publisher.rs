@@ -0,0 +1,247 @@
+use super::schema::ColumnType;
+use super::{coerce, dead_letter, DbClient, DbClientError, Page, SqlValue, TableQuery};
+use crate::setup_config::sql_server::{Publisher, TablePublication};
+use crate::MqttClient;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Publishes the new rows of tables to MQTT, the reverse of the forwarder.
+///
+/// Rows are read in the order of their watermark column, after the
+/// watermark of the last row published. Each is published as a JSON object
+/// and the watermark is saved once the broker has acknowledged the batch,
+/// or the rows of it delivered before a disconnection, so a restart neither
+/// misses rows nor publishes them again, but for those of a batch
+/// interrupted midway. Rows the broker rejects while connected are logged
+/// and skipped, not to block their table.
+pub struct TablePublisher<'a> {
+    db: &'a dyn DbClient,
+    mqtt: MqttClient,
+    config: Publisher,
+    /// Value of the watermark column of the last row published, by table.
+    watermarks: BTreeMap<String, Value>,
+}
+
+impl<'a> TablePublisher<'a> {
+    /// Resumes from the watermarks saved in the state file, if any.
+    pub fn new(
+        db: &'a dyn DbClient,
+        mqtt: MqttClient,
+        config: Publisher,
+    ) -> Result<Self, DbClientError> {
+        let watermarks = match std::fs::read_to_string(&config.state) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| {
+                DbClientError::Watermark(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(DbClientError::Watermark(e)),
+        };
+
+        Ok(TablePublisher {
+            db,
+            mqtt,
+            config,
+            watermarks,
+        })
+    }
+
+    /// Publishes the rows added since the last poll, returning how many.
+    pub async fn poll(&mut self) -> Result<usize, DbClientError> {
+        let mut published = 0;
+        let tables: Vec<_> = self.config.tables.keys().cloned().collect();
+        for table in tables {
+            loop {
+                let before = self.watermarks.get(&table).cloned();
+                let count = self.publish_batch(&table).await?;
+                published += count;
+                let full = count as u64 >= self.config.tables[&table].batch_size;
+                if !full || self.watermarks.get(&table) == before.as_ref() {
+                    break;
+                }
+            }
+        }
+        Ok(published)
+    }
+
+    /// Polls at the configured interval, logging failures. Never returns.
+    pub async fn run(&mut self) {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_millis(self.config.interval_ms));
+        loop {
+            interval.tick().await;
+            match self.poll().await {
+                Ok(0) => {}
+                Ok(count) => log::debug!("Published {} rows", count),
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+
+    /// The watermark of `table`, `None` before its first row.
+    pub fn watermark(&self, table: &str) -> Option<&Value> {
+        self.watermarks.get(table)
+    }
+
+    async fn publish_batch(&mut self, table: &str) -> Result<usize, DbClientError> {
+        let publication = &self.config.tables[table];
+        let query = TableQuery {
+            time_column: Some(publication.watermark.clone()),
+            after: self.watermarks.get(table).map(watermark_value),
+            page: Some(Page::first(publication.batch_size)),
+            ..Default::default()
+        };
+        let rows = self.db.query_table(table, &query).await?;
+
+        // The delivery of each row, if published, and its watermark.
+        let mut deliveries = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let Value::Object(row) = row else {
+                continue;
+            };
+            let token = match topic(table, publication, row) {
+                Ok(topic) => {
+                    let msg = paho_mqtt::MessageBuilder::new()
+                        .topic(topic)
+                        .payload(Value::from(row.clone()).to_string())
+                        .qos(publication.qos)
+                        .retained(publication.retained)
+                        .finalize();
+                    Some(self.mqtt.publish(msg))
+                }
+                // Never publishable, so skipped rather than retried.
+                Err(e) => {
+                    log::error!("Skipping row: {}", e);
+                    None
+                }
+            };
+            let watermark = row.get(&publication.watermark).filter(|v| !v.is_null());
+            deliveries.push((token, watermark.cloned()));
+        }
+
+        let mut last = None;
+        for (token, watermark) in deliveries {
+            let delivered = match token {
+                Some(token) => token.await,
+                None => Ok(()),
+            };
+            if let Err(source) = delivered {
+                // Rejected by the broker while connected, which would block
+                // the table if retried, so skipped as well.
+                if self.mqtt.is_connected() {
+                    log::error!("Skipping row of '{}': {}", table, source);
+                } else {
+                    // Not to publish the rows delivered so far again.
+                    self.advance(table, last)?;
+                    return Err(DbClientError::Publish {
+                        table: table.to_string(),
+                        source,
+                    });
+                }
+            }
+            last = watermark.or(last);
+        }
+
+        self.advance(table, last)?;
+        Ok(rows.len())
+    }
+
+    /// Saves `watermark` as that of `table`, if any.
+    fn advance(&mut self, table: &str, watermark: Option<Value>) -> Result<(), DbClientError> {
+        let Some(watermark) = watermark else {
+            return Ok(());
+        };
+        self.watermarks.insert(table.to_string(), watermark);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), DbClientError> {
+        let state = serde_json::to_string(&self.watermarks).unwrap_or_default();
+        dead_letter::rewrite_file(&self.config.state, &[state]).map_err(DbClientError::Watermark)
+    }
+}
+
+/// The topic of `row`, with each `{column}` of the template replaced by its
+/// value.
+fn topic(
+    table: &str,
+    publication: &TablePublication,
+    row: &Map<String, Value>,
+) -> Result<String, DbClientError> {
+    let mut out = String::new();
+    let mut rest = publication.topic.as_str();
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let column = &rest[start + 1..start + len];
+        let value = row
+            .get(column)
+            .ok_or_else(|| DbClientError::TopicTemplate {
+                table: table.to_string(),
+                column: column.to_string(),
+            })?;
+
+        out.push_str(&rest[..start]);
+        match value {
+            Value::String(s) => out.push_str(s),
+            other => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The watermark to bind, parsing the text of dates and times as written
+/// by [`crate::DbClient::query_table`].
+fn watermark_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Number(n) => SqlValue::from(n.clone()),
+        Value::String(s) => coerce::coerce(SqlValue::Text(s.clone()), &ColumnType::DateTime)
+            .unwrap_or_else(|_| SqlValue::Text(s.clone())),
+        Value::Bool(b) => SqlValue::Bool(*b),
+        other => SqlValue::Json(other.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn publication(topic: &str) -> TablePublication {
+        serde_json::from_value(json!({"watermark": "id", "topic": topic})).unwrap()
+    }
+
+    #[test]
+    fn fills_topic_templates() {
+        let row = json!({"device": "pump-1", "line": 3, "id": 7});
+        let row = row.as_object().unwrap();
+
+        assert_eq!(
+            topic("t", &publication("plant/{line}/{device}/state"), row).unwrap(),
+            "plant/3/pump-1/state"
+        );
+        assert_eq!(
+            topic("t", &publication("plant/all"), row).unwrap(),
+            "plant/all"
+        );
+        assert!(matches!(
+            topic("t", &publication("plant/{site}"), row),
+            Err(DbClientError::TopicTemplate { column, .. }) if column == "site"
+        ));
+    }
+
+    #[test]
+    fn binds_watermarks_by_type() {
+        assert_eq!(watermark_value(&json!(41)), SqlValue::Int(41));
+        assert_eq!(
+            watermark_value(&json!("2024-03-01 03:00:00.0")),
+            SqlValue::DateTime(time::macros::datetime!(2024-03-01 3:00))
+        );
+        assert_eq!(
+            watermark_value(&json!("batch-7")),
+            SqlValue::Text("batch-7".into())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use super::{identifier, Backend, DbClientError, SqlValue};
+
+/// Order of the rows of a [`TableQuery`], by its time column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// A page of rows, read with `LIMIT` and `OFFSET`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub limit: u64,
+    pub offset: u64,
+}
+
+impl Page {
+    /// The first `limit` rows.
+    pub fn first(limit: u64) -> Self {
+        Page { limit, offset: 0 }
+    }
+
+    /// The page following this one.
+    pub fn next(self) -> Self {
+        Page {
+            limit: self.limit,
+            offset: self.offset + self.limit,
+        }
+    }
+}
+
+/// Rows of a table within a time range, a page at a time.
+#[derive(Debug, Clone, Default)]
+pub struct TableQuery {
+    /// Column the range and order apply to. Querying a topic defaults to the
+    /// `received_at` metadata column of its mapping.
+    pub time_column: Option<String>,
+    /// Start of the range, inclusive, in UTC.
+    pub from: Option<time::PrimitiveDateTime>,
+    /// End of the range, exclusive, in UTC.
+    pub to: Option<time::PrimitiveDateTime>,
+    /// Only rows whose time column is greater than this value, of any
+    /// type, e.g. to resume after the last id read.
+    pub after: Option<SqlValue>,
+    pub order: Order,
+    /// All rows if `None`.
+    pub page: Option<Page>,
+}
+
+impl TableQuery {
+    /// Rows from `from` up to, not including, `to`.
+    pub fn between(from: time::PrimitiveDateTime, to: time::PrimitiveDateTime) -> Self {
+        TableQuery {
+            from: Some(from),
+            to: Some(to),
+            ..Default::default()
+        }
+    }
+}
+
+/// Builds the `SELECT` of `query` on `table`. Rows are unordered without a
+/// time column, which a range requires.
+pub(crate) fn select<'q, B: Backend>(
+    table: &str,
+    query: &TableQuery,
+) -> Result<sqlx::QueryBuilder<'q, B>, DbClientError> {
+    let quote = |name: &str| {
+        identifier::quote(name, B::DIALECT.quote).ok_or_else(|| DbClientError::InvalidIdentifier {
+            topic: None,
+            identifier: name.to_string(),
+        })
+    };
+
+    let mut builder = sqlx::QueryBuilder::new(format!("SELECT * FROM {}", quote(table)?));
+
+    let time_column = query.time_column.as_deref().map(quote).transpose()?;
+    let bounds = [
+        (">=", query.from.map(SqlValue::DateTime)),
+        ("<", query.to.map(SqlValue::DateTime)),
+        (">", query.after.clone()),
+    ];
+    let mut keyword = " WHERE ";
+    for (op, bound) in bounds {
+        let Some(bound) = bound else {
+            continue;
+        };
+        let column = time_column
+            .as_ref()
+            .ok_or_else(|| DbClientError::MissingTimeColumn {
+                table: table.to_string(),
+            })?;
+        builder.push(format!("{}{} {} ", keyword, column, op));
+        B::push_bind(&mut builder.separated(""), bound);
+        keyword = " AND ";
+    }
+
+    if let Some(column) = &time_column {
+        let direction = match query.order {
+            Order::Ascending => "ASC",
+            Order::Descending => "DESC",
+        };
+        builder.push(format!(" ORDER BY {} {}", column, direction));
+    }
+    if let Some(page) = query.page {
+        builder.push(format!(" LIMIT {} OFFSET {}", page.limit, page.offset));
+    }
+    Ok(builder)
+}
+
+#[cfg(all(test, feature = "mysql"))]
+mod test {
+    use super::*;
+    use time::macros::datetime;
+
+    fn sql(table: &str, query: &TableQuery) -> Result<String, DbClientError> {
+        select::<sqlx::MySql>(table, query).map(|b| b.sql().to_string())
+    }
+
+    #[test]
+    fn builds_range_queries() {
+        assert_eq!(
+            sql("events", &TableQuery::default()).unwrap(),
+            "SELECT * FROM `events`"
+        );
+
+        let query = TableQuery {
+            time_column: Some("at".into()),
+            order: Order::Descending,
+            page: Some(Page::first(10).next()),
+            ..TableQuery::between(datetime!(2024-03-01 0:00), datetime!(2024-03-02 0:00))
+        };
+        assert_eq!(
+            sql("events", &query).unwrap(),
+            "SELECT * FROM `events` WHERE `at` >= ? AND `at` < ? \
+             ORDER BY `at` DESC LIMIT 10 OFFSET 10"
+        );
+
+        let query = TableQuery {
+            time_column: Some("at".into()),
+            to: Some(datetime!(2024-03-02 0:00)),
+            ..Default::default()
+        };
+        assert_eq!(
+            sql("events", &query).unwrap(),
+            "SELECT * FROM `events` WHERE `at` < ? ORDER BY `at` ASC"
+        );
+
+        let query = TableQuery {
+            time_column: Some("id".into()),
+            after: Some(SqlValue::Int(41)),
+            page: Some(Page::first(100)),
+            ..Default::default()
+        };
+        assert_eq!(
+            sql("events", &query).unwrap(),
+            "SELECT * FROM `events` WHERE `id` > ? ORDER BY `id` ASC LIMIT 100 OFFSET 0"
+        );
+    }
+
+    #[test]
+    fn requires_a_time_column_for_ranges() {
+        let query = TableQuery::between(datetime!(2024-03-01 0:00), datetime!(2024-03-02 0:00));
+        assert!(matches!(
+            sql("events", &query),
+            Err(DbClientError::MissingTimeColumn { table }) if table == "events"
+        ));
+    }
+}
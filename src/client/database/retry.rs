@@ -0,0 +1,172 @@
+use super::dead_letter::{self, DeadLetter};
+use crate::setup_config::sql_server;
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/// Delay after `failures` consecutive failed attempts, doubling from the
+/// initial backoff up to the maximum.
+pub(crate) fn backoff(retry: &sql_server::Retry, failures: u32) -> Duration {
+    let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+    let delay = retry.initial_backoff_ms.saturating_mul(factor);
+    Duration::from_millis(delay.min(retry.max_backoff_ms))
+}
+
+/// Messages waiting for the database, oldest first: those in memory, then
+/// those in the spool file.
+pub(crate) struct Buffer {
+    config: sql_server::Buffer,
+    queue: VecDeque<DeadLetter>,
+    /// Messages in the spool file.
+    spooled: usize,
+    /// Failed attempts to write the buffered messages since the last
+    /// success.
+    pub(crate) failures: u32,
+    /// Time of the next attempt, any time if `None`.
+    pub(crate) next_drain: Option<Instant>,
+}
+
+impl Buffer {
+    /// Picks up the messages left in the spool file by a previous run.
+    pub fn new(config: sql_server::Buffer) -> Self {
+        let spooled = match &config.spool {
+            Some(path) => dead_letter::read_file(path).map_or_else(
+                |e| {
+                    log::error!("Cannot read spool '{}': {}", path.display(), e);
+                    0
+                },
+                |lines| lines.len(),
+            ),
+            None => 0,
+        };
+        if spooled > 0 {
+            log::info!("{} messages left in the spool", spooled);
+        }
+
+        Buffer {
+            config,
+            queue: VecDeque::new(),
+            spooled,
+            failures: 0,
+            next_drain: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len() + self.spooled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether writing the buffered messages may be attempted again.
+    pub fn is_due(&self) -> bool {
+        self.next_drain.is_none_or(|t| t <= Instant::now())
+    }
+
+    /// Adds `letter` after the others, `false` if the buffer is full.
+    pub fn push(&mut self, letter: DeadLetter) -> std::io::Result<bool> {
+        if self.config.capacity == 0 {
+            return Ok(false);
+        }
+        if self.spooled == 0 && self.queue.len() < self.config.capacity {
+            self.queue.push_back(letter);
+            return Ok(true);
+        }
+        let Some(path) = &self.config.spool else {
+            return Ok(false);
+        };
+        dead_letter::append_file(path, &letter)?;
+        self.spooled += 1;
+        Ok(true)
+    }
+
+    /// The oldest message, moving the oldest spooled ones to memory once it
+    /// is empty.
+    pub fn front(&mut self) -> std::io::Result<Option<&DeadLetter>> {
+        if self.queue.is_empty() && self.spooled > 0 {
+            self.refill()?;
+        }
+        Ok(self.queue.front())
+    }
+
+    pub fn pop_front(&mut self) {
+        self.queue.pop_front();
+    }
+
+    fn refill(&mut self) -> std::io::Result<()> {
+        let Some(path) = &self.config.spool else {
+            return Ok(());
+        };
+        let mut lines = dead_letter::read_file(path)?;
+        let rest = lines.split_off(lines.len().min(self.config.capacity.max(1)));
+
+        for line in lines {
+            match DeadLetter::from_json(&serde_json::Value::from(line.as_str())) {
+                Some(letter) => self.queue.push_back(letter),
+                None => log::warn!("Dropping malformed spooled message: {}", line),
+            }
+        }
+        dead_letter::rewrite_file(path, &rest)?;
+        self.spooled = rest.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MessageInfo;
+
+    #[test]
+    fn doubles_the_backoff() {
+        let retry = sql_server::Retry {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 350,
+        };
+        let delays: Vec<_> = (1..=4).map(|n| backoff(&retry, n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 350, 350]);
+        assert_eq!(backoff(&retry, u32::MAX).as_millis(), 350);
+    }
+
+    #[test]
+    fn spools_messages_beyond_capacity() {
+        let path = std::env::temp_dir().join(format!("spool-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let letter = |n: u8| DeadLetter::new(&MessageInfo::new("t"), &[b'0' + n], "unavailable");
+
+        let mut buffer = Buffer::new(sql_server::Buffer {
+            capacity: 2,
+            spool: Some(path.clone()),
+        });
+        for n in 0..5 {
+            assert!(buffer.push(letter(n)).unwrap());
+        }
+        assert_eq!(buffer.len(), 5);
+
+        // A restart picks up the spooled messages.
+        let restarted = Buffer::new(sql_server::Buffer {
+            capacity: 2,
+            spool: Some(path.clone()),
+        });
+        assert_eq!(restarted.len(), 3);
+
+        let mut payloads = Vec::new();
+        while let Some(letter) = buffer.front().unwrap() {
+            payloads.push(letter.payload.clone());
+            buffer.pop_front();
+        }
+        assert_eq!(payloads, [b"0", b"1", b"2", b"3", b"4"]);
+        assert!(buffer.is_empty());
+
+        let mut full = Buffer::new(sql_server::Buffer {
+            capacity: 1,
+            spool: None,
+        });
+        assert!(full.push(letter(0)).unwrap());
+        assert!(!full.push(letter(1)).unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use crate::setup_config::sql_server::NestedValues;
+use serde_json::{Map, Value};
+
+/// A payload value converted to the type it is bound to the query with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    /// Exact decimal number, kept as validated text.
+    Decimal(String),
+    Text(String),
+    /// Nested object or array, bound as its serialized JSON text.
+    Json(Value),
+    /// Date and time in UTC, or as given if the payload had no offset.
+    DateTime(time::PrimitiveDateTime),
+}
+
+impl From<serde_json::Number> for SqlValue {
+    fn from(value: serde_json::Number) -> Self {
+        if let Some(v) = value.as_i64() {
+            SqlValue::Int(v)
+        } else if let Some(v) = value.as_u64() {
+            SqlValue::UInt(v)
+        } else {
+            // serde_json numbers are always one of i64, u64 or a finite f64
+            SqlValue::Float(value.as_f64().unwrap_or_default())
+        }
+    }
+}
+
+/// The columns and values of a single row to insert.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Row {
+    pub columns: Vec<String>,
+    pub values: Vec<SqlValue>,
+}
+
+impl Row {
+    /// Converts a JSON object to a row, with nested objects and arrays
+    /// handled according to `nested`.
+    ///
+    /// Returns the name of the offending column if a nested value is
+    /// rejected.
+    pub fn from_object(data: Map<String, Value>, nested: &NestedValues) -> Result<Self, String> {
+        let mut out = Row::default();
+        for (k, v) in data {
+            out.push(k, v, nested)?;
+        }
+        Ok(out)
+    }
+
+    /// Sets the value of `column`, replacing the current one if any.
+    pub(crate) fn set(&mut self, column: &str, value: SqlValue) {
+        match self.columns.iter().position(|c| c == column) {
+            Some(i) => self.values[i] = value,
+            None => {
+                self.columns.push(column.to_string());
+                self.values.push(value);
+            }
+        }
+    }
+
+    /// Converts a JSON scalar, nested values are kept as JSON.
+    pub(crate) fn scalar(value: Value) -> SqlValue {
+        match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(o) => SqlValue::Bool(o),
+            Value::Number(o) => o.into(),
+            Value::String(o) => SqlValue::Text(o),
+            value => SqlValue::Json(value),
+        }
+    }
+
+    /// Appends `value` under `column`, or under several columns if it is
+    /// flattened.
+    pub(crate) fn push(
+        &mut self,
+        column: String,
+        value: Value,
+        nested: &NestedValues,
+    ) -> Result<(), String> {
+        let value = match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(o) => SqlValue::Bool(o),
+            Value::Number(o) => o.into(),
+            Value::String(o) => SqlValue::Text(o),
+            value @ (Value::Array(_) | Value::Object(_)) => match nested {
+                NestedValues::Json => SqlValue::Json(value),
+                NestedValues::Reject => return Err(column),
+                NestedValues::Flatten { separator } => {
+                    let children: Vec<(String, Value)> = match value {
+                        Value::Array(a) => a
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, v)| (i.to_string(), v))
+                            .collect(),
+                        Value::Object(o) => o.into_iter().collect(),
+                        _ => unreachable!(),
+                    };
+
+                    for (k, v) in children {
+                        self.push(format!("{}{}{}", column, separator, k), v, nested)?;
+                    }
+                    return Ok(());
+                }
+            },
+        };
+
+        self.columns.push(column);
+        self.values.push(value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn row(value: Value, nested: &NestedValues) -> Result<Row, String> {
+        match value {
+            Value::Object(o) => Row::from_object(o, nested),
+            _ => panic!("not an object"),
+        }
+    }
+
+    #[test]
+    fn converts_scalar_types() {
+        let out = row(
+            json!({"b": true, "f": 23.7, "i": -4, "n": null, "s": "x", "u": u64::MAX}),
+            &NestedValues::Reject,
+        )
+        .unwrap();
+
+        assert_eq!(out.columns, ["b", "f", "i", "n", "s", "u"]);
+        assert_eq!(
+            out.values,
+            [
+                SqlValue::Bool(true),
+                SqlValue::Float(23.7),
+                SqlValue::Int(-4),
+                SqlValue::Null,
+                SqlValue::Text("x".to_string()),
+                SqlValue::UInt(u64::MAX),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_nested_values() {
+        let payload = json!({"a": {"b": 1, "c": [true, null]}});
+
+        assert_eq!(
+            row(payload.clone(), &NestedValues::Reject),
+            Err("a".to_string())
+        );
+
+        let out = row(payload.clone(), &NestedValues::Json).unwrap();
+        assert_eq!(out.columns, ["a"]);
+        assert_eq!(out.values, [SqlValue::Json(payload["a"].clone())]);
+
+        let flatten = NestedValues::Flatten {
+            separator: ".".to_string(),
+        };
+        let out = row(payload, &flatten).unwrap();
+        assert_eq!(out.columns, ["a.b", "a.c.0", "a.c.1"]);
+        assert_eq!(
+            out.values,
+            [SqlValue::Int(1), SqlValue::Bool(true), SqlValue::Null]
+        );
+    }
+}
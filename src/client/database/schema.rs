@@ -0,0 +1,172 @@
+use super::backend::Backend;
+use super::dialect::Dialect;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Broad family of a column type, as far as value coercion is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer {
+        unsigned: bool,
+    },
+    /// A true boolean, unlike MySQL `BOOLEAN` which is a `TINYINT`.
+    Boolean,
+    Float,
+    Decimal,
+    DateTime,
+    Text,
+    Json,
+    /// Any other type, values are bound as they are.
+    Other(String),
+}
+
+impl ColumnType {
+    /// Maps the `DATA_TYPE` and `COLUMN_TYPE` of `INFORMATION_SCHEMA.COLUMNS`,
+    /// as reported by MySQL or PostgreSQL.
+    pub fn from_information_schema(data_type: &str, column_type: &str) -> Self {
+        match data_type.to_ascii_lowercase().as_str() {
+            "tinyint" | "smallint" | "mediumint" | "int" | "integer" | "bigint" | "year" => {
+                ColumnType::Integer {
+                    unsigned: column_type.to_ascii_lowercase().contains("unsigned"),
+                }
+            }
+            "boolean" => ColumnType::Boolean,
+            "float" | "double" | "real" | "double precision" => ColumnType::Float,
+            "decimal" | "numeric" => ColumnType::Decimal,
+            "datetime"
+            | "timestamp"
+            | "date"
+            | "timestamp without time zone"
+            | "timestamp with time zone" => ColumnType::DateTime,
+            "char" | "varchar" | "tinytext" | "text" | "mediumtext" | "longtext" | "enum"
+            | "set" | "character" | "character varying" => ColumnType::Text,
+            "json" | "jsonb" => ColumnType::Json,
+            other => ColumnType::Other(other.to_string()),
+        }
+    }
+}
+
+/// The columns of a table, keyed by lower-cased name where the database
+/// folds the case of column names, see [`Dialect::fold_column_case`].
+#[derive(Debug, Default)]
+pub struct TableSchema {
+    pub columns: HashMap<String, ColumnType>,
+    fold_case: bool,
+}
+
+impl TableSchema {
+    pub fn new(columns: impl IntoIterator<Item = (String, ColumnType)>, dialect: &Dialect) -> Self {
+        let fold_case = dialect.fold_column_case;
+        TableSchema {
+            columns: columns
+                .into_iter()
+                .map(|(name, column_type)| match fold_case {
+                    true => (name.to_lowercase(), column_type),
+                    false => (name, column_type),
+                })
+                .collect(),
+            fold_case,
+        }
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnType> {
+        match self.fold_case {
+            true => self.columns.get(&name.to_lowercase()),
+            false => self.columns.get(name),
+        }
+    }
+}
+
+/// Schemas of the mapped tables, as read from the database.
+#[derive(Default)]
+pub(crate) struct SchemaCache {
+    tables: RwLock<HashMap<String, Arc<TableSchema>>>,
+}
+
+impl SchemaCache {
+    /// The schema of `table`, read from the database if not cached yet.
+    pub(crate) async fn table<B: Backend>(
+        &self,
+        pool: &sqlx::Pool<B>,
+        table: &str,
+    ) -> Result<Arc<TableSchema>, sqlx::Error> {
+        if let Some(schema) = self.tables.read().await.get(table) {
+            return Ok(schema.clone());
+        }
+
+        self.reload(pool, table).await
+    }
+
+    /// Reads the schema of `table` again, e.g. after altering it.
+    pub(crate) async fn reload<B: Backend>(
+        &self,
+        pool: &sqlx::Pool<B>,
+        table: &str,
+    ) -> Result<Arc<TableSchema>, sqlx::Error> {
+        let schema = Arc::new(fetch(pool, table).await?);
+        self.tables
+            .write()
+            .await
+            .insert(table.to_string(), schema.clone());
+        Ok(schema)
+    }
+
+    /// Replaces the cache with freshly read schemas of `tables`.
+    pub(crate) async fn refresh<'a, B: Backend>(
+        &self,
+        pool: &sqlx::Pool<B>,
+        tables: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), (String, sqlx::Error)> {
+        let mut out = HashMap::new();
+        for table in tables {
+            let schema = fetch(pool, table)
+                .await
+                .map_err(|e| (table.to_string(), e))?;
+            out.insert(table.to_string(), Arc::new(schema));
+        }
+
+        *self.tables.write().await = out;
+        Ok(())
+    }
+}
+
+async fn fetch<B: Backend>(pool: &sqlx::Pool<B>, table: &str) -> Result<TableSchema, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let columns = B::fetch_columns(&mut conn, table).await?;
+
+    if columns.is_empty() {
+        log::warn!("Table '{}' has no columns or does not exist", table);
+    }
+
+    Ok(TableSchema::new(columns, &B::DIALECT))
+}
+
+#[cfg(all(test, feature = "mysql"))]
+mod test {
+    use super::*;
+
+    fn columns() -> Vec<(String, ColumnType)> {
+        vec![
+            ("Temp".to_string(), ColumnType::Float),
+            ("temp".to_string(), ColumnType::Text),
+        ]
+    }
+
+    #[test]
+    fn folds_column_case_by_dialect() {
+        let exact = Dialect {
+            fold_column_case: false,
+            ..Dialect::MYSQL
+        };
+        let schema = TableSchema::new(columns(), &exact);
+        assert_eq!(schema.column("Temp"), Some(&ColumnType::Float));
+        assert_eq!(schema.column("temp"), Some(&ColumnType::Text));
+        assert_eq!(schema.column("TEMP"), None);
+        assert!(!exact.same_column("Temp", "temp"));
+
+        let schema = TableSchema::new(vec![columns().remove(0)], &Dialect::MYSQL);
+        assert_eq!(schema.column("TEMP"), Some(&ColumnType::Float));
+        assert!(Dialect::MYSQL.same_column("Temp", "temp"));
+    }
+}
@@ -0,0 +1,203 @@
+use super::dialect::{Dialect, Upsert};
+use super::identifier;
+use crate::setup_config::sql_server::WriteMode;
+
+/// Why a statement could not be built.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatementError {
+    InvalidIdentifier(String),
+    /// The write mode is not supported by the database.
+    UnsupportedMode,
+}
+
+/// Start of the statement writing rows of `columns` into `table`, before the
+/// `VALUES` list, and the clause following it.
+pub fn statement(
+    table: &str,
+    columns: &[String],
+    mode: &WriteMode,
+    dialect: &Dialect,
+) -> Result<(String, String), StatementError> {
+    let quote = |name: &String| {
+        identifier::quote(name, dialect.quote)
+            .ok_or_else(|| StatementError::InvalidIdentifier(name.clone()))
+    };
+    let quoted_table = quote(&table.to_string())?;
+    let quoted_columns = identifier::quote_list(columns, dialect.quote)
+        .map_err(StatementError::InvalidIdentifier)?;
+
+    let verb = match mode {
+        WriteMode::Append | WriteMode::Upsert { .. } | WriteMode::Deduplicate { .. } => {
+            "INSERT INTO"
+        }
+        WriteMode::Replace => dialect.replace.ok_or(StatementError::UnsupportedMode)?,
+    };
+    let head = format!("{} {} ({}) ", verb, quoted_table, quoted_columns);
+
+    let Some(keys) = mode.keys() else {
+        return Ok((head, String::new()));
+    };
+
+    let mut updates = Vec::new();
+    // Deduplicating updates nothing, as an upsert of the key columns alone.
+    let updated = match mode {
+        WriteMode::Deduplicate { .. } => &[][..],
+        _ => columns,
+    };
+    for column in updated {
+        if keys.iter().any(|k| dialect.same_column(k, column)) {
+            continue;
+        }
+        let quoted = quote(column)?;
+        updates.push(match dialect.upsert {
+            Upsert::OnDuplicateKey => format!("{0} = VALUES({0})", quoted),
+            Upsert::OnConflict => format!("{0} = EXCLUDED.{0}", quoted),
+        });
+    }
+
+    let tail = match dialect.upsert {
+        Upsert::OnDuplicateKey => {
+            // Only key columns: a no-op update keeps the existing row.
+            if updates.is_empty() {
+                let key = keys.first().ok_or(StatementError::UnsupportedMode)?;
+                let quoted = quote(key)?;
+                updates.push(format!("{0} = {0}", quoted));
+            }
+            format!(" ON DUPLICATE KEY UPDATE {}", updates.join(", "))
+        }
+        Upsert::OnConflict => {
+            let target = identifier::quote_list(keys, dialect.quote)
+                .map_err(StatementError::InvalidIdentifier)?;
+            if updates.is_empty() {
+                format!(" ON CONFLICT ({}) DO NOTHING", target)
+            } else {
+                format!(
+                    " ON CONFLICT ({}) DO UPDATE SET {}",
+                    target,
+                    updates.join(", ")
+                )
+            }
+        }
+    };
+
+    Ok((head, tail))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn columns(names: &[&str]) -> Vec<String> {
+        names.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn builds_statements_per_mode() {
+        let mysql = Dialect::MYSQL;
+        let cols = columns(&["device", "temp"]);
+
+        assert_eq!(
+            statement("state", &cols, &WriteMode::Append, &mysql).unwrap(),
+            (
+                "INSERT INTO `state` (`device`, `temp`) ".to_string(),
+                String::new()
+            )
+        );
+        assert_eq!(
+            statement("state", &cols, &WriteMode::Replace, &mysql)
+                .unwrap()
+                .0,
+            "REPLACE INTO `state` (`device`, `temp`) "
+        );
+
+        let upsert = WriteMode::Upsert {
+            keys: columns(&["Device"]),
+        };
+        assert_eq!(
+            statement("state", &cols, &upsert, &mysql).unwrap().1,
+            " ON DUPLICATE KEY UPDATE `temp` = VALUES(`temp`)"
+        );
+        assert_eq!(
+            statement("state", &columns(&["device"]), &upsert, &mysql)
+                .unwrap()
+                .1,
+            " ON DUPLICATE KEY UPDATE `Device` = `Device`"
+        );
+        let deduplicate = WriteMode::Deduplicate {
+            keys: columns(&["device"]),
+        };
+        assert_eq!(
+            statement("state", &cols, &deduplicate, &mysql).unwrap().1,
+            " ON DUPLICATE KEY UPDATE `device` = `device`"
+        );
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn builds_postgres_upserts() {
+        let postgres = Dialect::POSTGRES;
+        let upsert = WriteMode::Upsert {
+            keys: columns(&["device"]),
+        };
+
+        assert_eq!(
+            statement("state", &columns(&["device", "temp"]), &upsert, &postgres)
+                .unwrap()
+                .1,
+            " ON CONFLICT (\"device\") DO UPDATE SET \"temp\" = EXCLUDED.\"temp\""
+        );
+        assert_eq!(
+            statement("state", &columns(&["device"]), &upsert, &postgres)
+                .unwrap()
+                .1,
+            " ON CONFLICT (\"device\") DO NOTHING"
+        );
+        let deduplicate = WriteMode::Deduplicate {
+            keys: columns(&["device", "at"]),
+        };
+        assert_eq!(
+            statement(
+                "log",
+                &columns(&["device", "at", "temp"]),
+                &deduplicate,
+                &postgres
+            )
+            .unwrap()
+            .1,
+            " ON CONFLICT (\"device\", \"at\") DO NOTHING"
+        );
+        assert_eq!(
+            statement(
+                "state",
+                &columns(&["device"]),
+                &WriteMode::Replace,
+                &postgres
+            ),
+            Err(StatementError::UnsupportedMode)
+        );
+    }
+
+    #[test]
+    fn deserializes_write_modes() {
+        use crate::setup_config::sql_server::TableMapping;
+
+        let m: TableMapping = serde_json::from_value(serde_json::json!({
+            "table": "state",
+            "write": {"upsert": {"keys": ["device"]}},
+        }))
+        .unwrap();
+        assert_eq!(
+            m.write,
+            WriteMode::Upsert {
+                keys: columns(&["device"])
+            }
+        );
+
+        let m: TableMapping =
+            serde_json::from_value(serde_json::json!({"table": "state", "write": "replace"}))
+                .unwrap();
+        assert_eq!(m.write, WriteMode::Replace);
+        assert_eq!(TableMapping::from("log").write, WriteMode::Append);
+    }
+}
@@ -1,6 +1,9 @@
-// mod database;
+pub mod app_config;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+mod database;
 mod mqtt;
-// pub mod setup_config;
+pub mod setup_config;
 
-// pub use database::*;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub use database::*;
 pub use mqtt::*;
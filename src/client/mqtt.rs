@@ -2,7 +2,7 @@ use futures_util::StreamExt;
 use paho::MQTT_VERSION_5;
 use paho_mqtt as paho;
 use serde::Deserialize;
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 pub mod deserialized {
     use super::*;
@@ -11,7 +11,7 @@ pub mod deserialized {
     pub mod subscribe_options {
         use super::*;
 
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Copy)]
         pub struct RetainHandling {
             pub(crate) inner: paho::RetainHandling,
         }
@@ -83,8 +83,8 @@ pub mod deserialized {
 
     #[derive(Debug, Deserialize)]
     pub struct Properties {}
-    impl Into<paho::Properties> for Properties {
-        fn into(self) -> paho::Properties {
+    impl From<Properties> for paho::Properties {
+        fn from(_: Properties) -> Self {
             // TODO: actually read
             paho::Properties::default()
         }
@@ -139,6 +139,26 @@ pub mod deserialized {
         pub(crate) subscription_props: Option<Properties>,
         pub(crate) subscriptions: Subscriptions,
     }
+
+    impl MqttClientConfig {
+        pub fn client_id(&self) -> &str {
+            &self.client_id
+        }
+
+        /// Connects as `client_id` instead, e.g. for a tool running next to
+        /// the service the file configures, whose session it would take over.
+        pub fn with_client_id(mut self, client_id: impl Into<String>) -> Self {
+            self.client_id = client_id.into();
+            self
+        }
+
+        /// Drops the configured subscriptions, to subscribe on demand with
+        /// [`MqttClient::subscribe`](super::MqttClient::subscribe).
+        pub fn without_subscriptions(mut self) -> Self {
+            self.subscriptions = Subscriptions::default();
+            self
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -189,10 +209,7 @@ impl From<deserialized::MqttClientConfig> for MqttClientConfig {
         };
 
         let subscriptions = value.subscriptions.into();
-        let subscription_props = match value.subscription_props {
-            Some(p) => Some(p.into()),
-            None => None,
-        };
+        let subscription_props = value.subscription_props.map(|p| p.into());
 
         Self {
             mqtt_create_options,
@@ -215,16 +232,33 @@ impl Default for MqttClientConfig {
     }
 }
 
+/// Cheaply clonable handle for publishing, subscribing and querying the
+/// connection state of a running client.
+///
+/// Messages are consumed through the [`MqttReceiver`] returned alongside it
+/// by [`MqttClient::start`].
+#[derive(Clone)]
 pub struct MqttClient {
     pub(crate) mqtt_client: paho::AsyncClient,
-    pub(crate) mqtt_subscription_stream: paho::AsyncReceiver<Option<paho::Message>>,
+    /// Also held for the whole duration of a reconnection, so that only one
+    /// task drives it at a time.
+    pub(crate) connection: Arc<tokio::sync::Mutex<ConnectionData>>,
+}
+
+pub(crate) struct ConnectionData {
     pub(crate) mqtt_connect_opt: paho::ConnectOptions,
     pub(crate) mqtt_subscription_props: Option<paho::Properties>,
     pub(crate) mqtt_subscriptions: SubscriptionData,
 }
 
+/// The single consumer of the messages received by a [`MqttClient`].
+pub struct MqttReceiver {
+    pub(crate) client: MqttClient,
+    pub(crate) mqtt_subscription_stream: paho::AsyncReceiver<Option<paho::Message>>,
+}
+
 impl MqttClient {
-    pub async fn start(config: MqttClientConfig) -> Self {
+    pub async fn start(config: MqttClientConfig) -> (Self, MqttReceiver) {
         let mut mqtt_client = match paho::AsyncClient::new(config.mqtt_create_options) {
             Ok(c) => c,
             Err(e) => {
@@ -234,33 +268,66 @@ impl MqttClient {
         };
 
         let mqtt_subscription_stream = mqtt_client.get_stream(config.msg_buffer_limit);
-        let out = MqttClient {
+        let client = MqttClient {
             mqtt_client,
+            connection: Arc::new(tokio::sync::Mutex::new(ConnectionData {
+                mqtt_connect_opt: config.mqtt_connect_options,
+                mqtt_subscription_props: config.subscription_props,
+                mqtt_subscriptions: config.subscriptions,
+            })),
+        };
+
+        client.connect().await;
+
+        let receiver = MqttReceiver {
+            client: client.clone(),
             mqtt_subscription_stream,
-            mqtt_connect_opt: config.mqtt_connect_options,
-            mqtt_subscription_props: config.subscription_props,
-            mqtt_subscriptions: config.subscriptions,
         };
+        (client, receiver)
+    }
 
-        Self::connect(&out).await;
-        out
+    pub fn publish(&self, msg: paho::Message) -> paho::DeliveryToken {
+        self.mqtt_client.publish(msg)
     }
 
-    pub async fn poll(&mut self) -> Option<paho::Message> {
-        if !self.mqtt_client.is_connected() {
-            self.reconnect().await;
-        }
+    pub fn subscribe(&self, topic: &str, qos: i32) -> paho::SubscribeToken {
+        self.mqtt_client.subscribe(topic, qos)
+    }
 
-        self.mqtt_subscription_stream.next().await?
+    pub fn unsubscribe(&self, topic: &str) -> paho::Token {
+        self.mqtt_client.unsubscribe(topic)
     }
 
-    pub fn publish(&self, msg: paho::Message) -> paho::DeliveryToken {
-        self.mqtt_client.publish(msg)
+    /// Disconnects from the broker, which keeps the session, and the
+    /// messages of its subscriptions, until it expires.
+    pub fn disconnect(&self) -> paho::Token {
+        self.mqtt_client.disconnect(None)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.mqtt_client.is_connected()
+    }
+
+    pub fn client_id(&self) -> String {
+        self.mqtt_client.client_id()
     }
 
+    pub fn server_uri(&self) -> String {
+        self.mqtt_client.server_uri()
+    }
+
+    /// Blocks until the connection to the broker is restored.
+    ///
+    /// Only one task drives the reconnection at a time, the others wait for
+    /// it to finish.
     pub async fn reconnect(&self) {
         use tokio::time::sleep;
 
+        let _guard = self.connection.lock().await;
+        if self.mqtt_client.is_connected() {
+            return;
+        }
+
         let host = self.mqtt_client.server_uri();
 
         let log_lost_connection = |hostname: String| {
@@ -292,10 +359,11 @@ impl MqttClient {
 
     async fn connect(&self) {
         let host = self.mqtt_client.server_uri();
+        let connection = self.connection.lock().await;
 
         while (self
             .mqtt_client
-            .connect(self.mqtt_connect_opt.clone())
+            .connect(connection.mqtt_connect_opt.clone())
             .await)
             .is_err()
         {
@@ -303,12 +371,17 @@ impl MqttClient {
         }
         log::info!("Connected to broker '{}'", host);
 
+        if connection.mqtt_subscriptions.topics.is_empty() {
+            return;
+        }
         let mut subscription = self.mqtt_client.subscribe_many_with_options(
-            &self.mqtt_subscriptions.topics,
-            &self.mqtt_subscriptions.qos,
-            &self.mqtt_subscriptions.opts,
-            self.mqtt_subscription_props.clone(),
+            &connection.mqtt_subscriptions.topics,
+            &connection.mqtt_subscriptions.qos,
+            &connection.mqtt_subscriptions.opts,
+            connection.mqtt_subscription_props.clone(),
         );
+        let topics = connection.mqtt_subscriptions.topics.clone();
+        drop(connection);
 
         // while self.mqtt_client.is_connected() {}
 
@@ -323,9 +396,48 @@ impl MqttClient {
             }
         }
 
-        log::info!("Subscribed to topics: {:?}", self.mqtt_subscriptions.topics);
+        log::info!("Subscribed to topics: {:?}", topics);
+    }
+}
+
+impl MqttReceiver {
+    pub async fn poll(&mut self) -> Option<paho::Message> {
+        if !self.client.is_connected() {
+            self.client.reconnect().await;
+        }
+
+        self.mqtt_subscription_stream.next().await?
+    }
+
+    /// The next message already received, without waiting for one nor
+    /// reconnecting, e.g. to handle those left once disconnected.
+    pub fn try_poll(&mut self) -> Option<paho::Message> {
+        loop {
+            match self.mqtt_subscription_stream.try_recv() {
+                Ok(Some(msg)) => return Some(msg),
+                // Marks a disconnection.
+                Ok(None) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// The handle of the client this receiver consumes messages from.
+    pub fn client(&self) -> &MqttClient {
+        &self.client
     }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_handle_is_shareable_across_tasks() {
+        fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+        fn assert_sendable<T: Send + 'static>() {}
+
+        assert_shareable::<MqttClient>();
+        assert_sendable::<MqttReceiver>();
+    }
+}
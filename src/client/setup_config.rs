@@ -1,4 +1,5 @@
-use std::{fs::File, io::BufReader, path::PathBuf};
+use crate::app_config::{self, ConfigError};
+use std::path::PathBuf;
 
 #[derive(Debug, serde::Deserialize)]
 pub struct MqttClientOptions {
@@ -8,23 +9,23 @@ pub struct MqttClientOptions {
 }
 
 impl TryFrom<PathBuf> for MqttClientOptions {
-    type Error = std::io::Error;
+    type Error = ConfigError;
 
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
-        let file = File::open(path)?;
-        let config_reader = BufReader::new(file);
-        Ok(serde_json::from_reader(config_reader).expect("Failed to deserialize JSON"))
+        app_config::load(path, "mqtt")
     }
 }
 
 pub mod mqtt {
+    use crate::deserialized::subscribe_options::RetainHandling;
     use std::collections::HashMap;
 
     #[derive(Debug, serde::Deserialize, Clone, Copy)]
     pub struct TopicOptions {
         pub no_local: bool,
         pub retain_as_publish: bool,
-        pub retain_handling: i32,
+        /// 0, 1 or 2, rejected when loaded otherwise.
+        pub retain_handling: RetainHandling,
     }
 
     impl From<TopicOptions> for paho_mqtt::SubscribeOptions {
@@ -32,12 +33,7 @@ pub mod mqtt {
             paho_mqtt::SubscribeOptionsBuilder::new()
                 .no_local(value.no_local)
                 .retain_as_published(value.retain_as_publish)
-                .retain_handling(
-                    value
-                        .retain_handling
-                        .try_into()
-                        .expect("valid input is 0, 1, and 2"),
-                )
+                .retain_handling(value.retain_handling.inner)
                 .finalize()
         }
     }
@@ -53,26 +49,602 @@ pub mod mqtt {
 
 #[derive(Debug, serde::Deserialize)]
 pub struct SqlServerSetupConfig {
+    #[serde(default)]
+    pub driver: sql_server::Driver,
+    /// Unused by SQLite.
+    #[serde(default)]
     pub host: String,
+    /// Unused by SQLite.
+    #[serde(default)]
     pub username: String,
     pub password: Option<String>,
+    /// Name of the database, or for SQLite the path of the database file,
+    /// `:memory:` for a database in memory.
     pub database: String,
     pub port: Option<u16>,
+    /// Also filled from the forwarding rules of an
+    /// [`AppConfig`](crate::app_config::AppConfig).
+    #[serde(default)]
     pub topic_table_map: sql_server::TopicTableMapping,
+    #[serde(default)]
+    pub nested_values: sql_server::NestedValues,
+    #[serde(default)]
+    pub unknown_columns: sql_server::UnknownColumns,
+    /// Convert payload values to the types of their columns, as read from
+    /// the table schemas.
+    #[serde(default = "sql_server::default_coerce_types")]
+    pub coerce_types: bool,
+    #[serde(default)]
+    pub schema_evolution: sql_server::SchemaEvolution,
+    /// Where messages that could not be written are recorded, not at all
+    /// if `None`.
+    #[serde(default)]
+    pub dead_letter: Option<sql_server::DeadLetterStore>,
+    #[serde(default)]
+    pub retry: sql_server::Retry,
+    #[serde(default)]
+    pub buffer: sql_server::Buffer,
+    #[serde(default)]
+    pub pool: sql_server::Pool,
+    /// TLS of the connections, the driver's default if `None`. Unused by
+    /// SQLite.
+    #[serde(default)]
+    pub ssl: Option<sql_server::Ssl>,
+    /// Character set of the connections, `utf8mb4` by default. MySQL only.
+    pub charset: Option<String>,
+    /// Collation of the connections, that of the character set by default.
+    /// MySQL only.
+    pub collation: Option<String>,
+    /// Time zone of the sessions, e.g. `+08:00`, UTC by default. Unused by
+    /// SQLite.
+    pub timezone: Option<String>,
+    /// Tables whose new rows are published to MQTT, none if `None`.
+    #[serde(default)]
+    pub publisher: Option<sql_server::Publisher>,
 }
 
 impl TryFrom<PathBuf> for SqlServerSetupConfig {
-    type Error = std::io::Error;
+    type Error = ConfigError;
 
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
-        let file = File::open(path)?;
-        let config_reader = BufReader::new(file);
-        Ok(serde_json::from_reader(config_reader).expect("Failed to deserialize JSON"))
+        app_config::load(path, "database")
     }
 }
 
 pub mod sql_server {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+
+    pub type TopicTableMapping = HashMap<String, TopicMapping>;
+
+    /// Database the forwarder writes to, each behind the cargo feature of
+    /// the same name.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Driver {
+        #[default]
+        Mysql,
+        Postgres,
+        Sqlite,
+    }
+
+    impl Driver {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Driver::Mysql => "mysql",
+                Driver::Postgres => "postgres",
+                Driver::Sqlite => "sqlite",
+            }
+        }
+    }
+
+    /// The tables the payloads of a topic are written to, all within one
+    /// transaction.
+    ///
+    /// Deserializes either from a single [`TableMapping`] or from a list of
+    /// them.
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    #[serde(from = "TopicMappingRepr")]
+    pub struct TopicMapping {
+        pub tables: Vec<TableMapping>,
+    }
+
+    impl From<TableMapping> for TopicMapping {
+        fn from(mapping: TableMapping) -> Self {
+            TopicMapping {
+                tables: vec![mapping],
+            }
+        }
+    }
+
+    impl From<&str> for TopicMapping {
+        fn from(table: &str) -> Self {
+            TableMapping::from(table).into()
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum TopicMappingRepr {
+        One(TableMapping),
+        Many(Vec<TableMapping>),
+    }
+
+    impl From<TopicMappingRepr> for TopicMapping {
+        fn from(value: TopicMappingRepr) -> Self {
+            match value {
+                TopicMappingRepr::One(mapping) => mapping.into(),
+                TopicMappingRepr::Many(tables) => TopicMapping { tables },
+            }
+        }
+    }
+
+    /// Where and how the payloads of a topic are written.
+    ///
+    /// Deserializes either from a bare table name, writing every payload key
+    /// to the column of the same name, or from an object with field rules.
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    #[serde(from = "TableMappingRepr")]
+    pub struct TableMapping {
+        pub table: String,
+        /// Rules producing each column, keyed by column name.
+        pub fields: BTreeMap<String, FieldRule>,
+        /// Also write the payload keys not used by any rule, under their
+        /// own name. Always the case when there are no rules.
+        pub include_unmapped: bool,
+        pub metadata: MetadataColumns,
+        pub write: WriteMode,
+    }
+
+    impl From<&str> for TableMapping {
+        fn from(table: &str) -> Self {
+            TableMapping {
+                table: table.to_string(),
+                fields: BTreeMap::new(),
+                include_unmapped: true,
+                metadata: MetadataColumns::default(),
+                write: WriteMode::default(),
+            }
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum TableMappingRepr {
+        Table(String),
+        Mapping {
+            table: String,
+            #[serde(default)]
+            fields: BTreeMap<String, FieldRule>,
+            #[serde(default)]
+            include_unmapped: bool,
+            #[serde(default)]
+            metadata: MetadataColumns,
+            #[serde(default)]
+            write: WriteMode,
+        },
+    }
+
+    impl From<TableMappingRepr> for TableMapping {
+        fn from(value: TableMappingRepr) -> Self {
+            match value {
+                TableMappingRepr::Table(table) => table.as_str().into(),
+                TableMappingRepr::Mapping {
+                    table,
+                    fields,
+                    include_unmapped,
+                    metadata,
+                    write,
+                } => TableMapping {
+                    include_unmapped: include_unmapped || fields.is_empty(),
+                    table,
+                    fields,
+                    metadata,
+                    write,
+                },
+            }
+        }
+    }
+
+    /// How rows are written to the table of a mapping.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum WriteMode {
+        /// Insert every message as a new row.
+        #[default]
+        Append,
+        /// Insert the row, or update the non-key columns of the row
+        /// conflicting with it on a primary or unique key, e.g. to keep the
+        /// latest state per device.
+        ///
+        /// `keys` are the columns of that key, which every row must hold and
+        /// which are never updated.
+        Upsert { keys: Vec<String> },
+        /// `REPLACE` the row conflicting on a primary or unique key, which
+        /// deletes it first, resetting the columns the row does not hold.
+        Replace,
+        /// Insert the row unless it conflicts on a primary or unique key,
+        /// keeping the existing row, e.g. to drop messages delivered again.
+        ///
+        /// `keys` are the columns of that key, which every row must hold.
+        Deduplicate { keys: Vec<String> },
+    }
+
+    impl WriteMode {
+        /// Columns of the key rows conflict on, if the mode has one.
+        pub fn keys(&self) -> Option<&[String]> {
+            match self {
+                WriteMode::Upsert { keys } | WriteMode::Deduplicate { keys } => Some(keys),
+                WriteMode::Append | WriteMode::Replace => None,
+            }
+        }
+    }
+
+    /// How the value of a column is produced.
+    ///
+    /// A bare string is shorthand for `{"pointer": ...}` if it starts with
+    /// `/`, and for `{"key": ...}` otherwise.
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    #[serde(from = "FieldRuleRepr")]
+    pub struct FieldRule {
+        pub source: FieldSource,
+        /// Values substituted for the source strings, e.g. `{"ON": 1}`.
+        pub map: Option<HashMap<String, serde_json::Value>>,
+        pub unit: Option<UnitConversion>,
+        pub scale: Option<f64>,
+        pub offset: Option<f64>,
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum FieldRuleRepr {
+        Path(String),
+        Rule {
+            #[serde(flatten)]
+            source: FieldSource,
+            #[serde(default, rename = "enum")]
+            map: Option<HashMap<String, serde_json::Value>>,
+            #[serde(default)]
+            unit: Option<UnitConversion>,
+            #[serde(default)]
+            scale: Option<f64>,
+            #[serde(default)]
+            offset: Option<f64>,
+        },
+    }
+
+    impl From<FieldRuleRepr> for FieldRule {
+        fn from(value: FieldRuleRepr) -> Self {
+            match value {
+                FieldRuleRepr::Path(path) => FieldRule {
+                    source: if path.starts_with('/') {
+                        FieldSource::Pointer(path)
+                    } else {
+                        FieldSource::Key(path)
+                    },
+                    map: None,
+                    unit: None,
+                    scale: None,
+                    offset: None,
+                },
+                FieldRuleRepr::Rule {
+                    source,
+                    map,
+                    unit,
+                    scale,
+                    offset,
+                } => FieldRule {
+                    source,
+                    map,
+                    unit,
+                    scale,
+                    offset,
+                },
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum FieldSource {
+        /// Top level key of the payload.
+        Key(String),
+        /// JSON pointer into the payload, e.g. `/sensors/0/temp`.
+        Pointer(String),
+        /// Constant value.
+        Value(serde_json::Value),
+        /// Property of the message rather than of its payload.
+        Meta(Metadata),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Metadata {
+        Topic,
+        /// Level of the topic at the given index, starting from 0.
+        TopicSegment(usize),
+        /// When the forwarder received the message.
+        ReceivedAt,
+        Qos,
+        Retained,
+        /// Every MQTT v5 user property, as a JSON object.
+        UserProperties,
+        /// The MQTT v5 user property of the given name.
+        UserProperty(String),
+        /// Client id of the forwarder that received the message.
+        ClientId,
+    }
+
+    /// Columns filled with properties of the message, each written under
+    /// the given column name.
+    ///
+    /// These take precedence over payload keys of the same name.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+    pub struct MetadataColumns {
+        #[serde(default)]
+        pub received_at: Option<String>,
+        #[serde(default)]
+        pub topic: Option<String>,
+        /// Column of each topic level, keyed by index starting from 0.
+        #[serde(default, deserialize_with = "deserialize_segments")]
+        pub topic_segments: BTreeMap<usize, String>,
+        #[serde(default)]
+        pub user_properties: Option<String>,
+        #[serde(default)]
+        pub client_id: Option<String>,
+    }
 
-    pub type TopicTableMapping = HashMap<String, String>;
+    impl MetadataColumns {
+        /// Each column with the metadata it holds.
+        pub fn columns(&self) -> Vec<(&str, Metadata)> {
+            let mut out = Vec::new();
+            if let Some(c) = &self.received_at {
+                out.push((c.as_str(), Metadata::ReceivedAt));
+            }
+            if let Some(c) = &self.topic {
+                out.push((c.as_str(), Metadata::Topic));
+            }
+            for (i, c) in &self.topic_segments {
+                out.push((c.as_str(), Metadata::TopicSegment(*i)));
+            }
+            if let Some(c) = &self.user_properties {
+                out.push((c.as_str(), Metadata::UserProperties));
+            }
+            if let Some(c) = &self.client_id {
+                out.push((c.as_str(), Metadata::ClientId));
+            }
+            out
+        }
+    }
+
+    // Integer map keys are not supported inside the untagged
+    // `TableMappingRepr`, so they are parsed from strings here.
+    fn deserialize_segments<'de, D>(deserializer: D) -> Result<BTreeMap<usize, String>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::{de::Error, Deserialize};
+
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(index, column)| match index.parse() {
+                Ok(i) => Ok((i, column)),
+                Err(_) => Err(D::Error::custom(format!(
+                    "invalid topic segment index '{}'",
+                    index
+                ))),
+            })
+            .collect()
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    pub struct UnitConversion {
+        pub from: String,
+        pub to: String,
+    }
+
+    /// How nested objects and arrays in a payload are written.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum NestedValues {
+        /// Store the value as JSON text, e.g. in a `JSON` column.
+        Json,
+        /// Spread the value over one column per leaf, named by joining the
+        /// keys (or array indices) with `separator`.
+        Flatten {
+            #[serde(default = "default_separator")]
+            separator: String,
+        },
+        /// Fail the push.
+        #[default]
+        Reject,
+    }
+
+    /// How payload keys without a matching column in the table schema are
+    /// handled.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum UnknownColumns {
+        /// Insert every key, letting the database reject unknown columns.
+        #[default]
+        Allow,
+        /// Silently leave out unknown keys.
+        Drop,
+        /// Fail the push.
+        Reject,
+    }
+
+    /// Opt-in changes to the database schema driven by the payloads.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+    pub struct SchemaEvolution {
+        /// Create missing mapped tables from the types of the first payload.
+        #[serde(default)]
+        pub create_tables: bool,
+        /// Add columns for payload keys missing from the table.
+        #[serde(default)]
+        pub add_columns: bool,
+        /// Log the statements instead of executing them.
+        #[serde(default)]
+        pub dry_run: bool,
+    }
+
+    impl SchemaEvolution {
+        pub fn is_enabled(&self) -> bool {
+            self.create_tables || self.add_columns
+        }
+    }
+
+    /// Where messages that could not be written are recorded, to be
+    /// replayed once the mapping or schema is fixed.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum DeadLetterStore {
+        /// A table of the database, created if missing.
+        Table { table: String },
+        /// A file of JSON records, one per line.
+        File { path: std::path::PathBuf },
+        /// JSON records published to an MQTT topic, through the client
+        /// given with `set_dead_letter_publisher`.
+        Republish {
+            topic: String,
+            #[serde(default)]
+            qos: i32,
+        },
+    }
+
+    /// Retries of a write failing with a transient error, such as a lost
+    /// connection or a deadlock, waiting twice as long after each attempt.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Retry {
+        /// Attempts of a write before the message is buffered.
+        pub max_attempts: u32,
+        pub initial_backoff_ms: u64,
+        pub max_backoff_ms: u64,
+    }
+
+    impl Default for Retry {
+        fn default() -> Self {
+            Retry {
+                max_attempts: 3,
+                initial_backoff_ms: 100,
+                max_backoff_ms: 10_000,
+            }
+        }
+    }
+
+    /// Messages kept while the database is unavailable, written in the
+    /// order they were received once it is back.
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Buffer {
+        /// Messages kept in memory, none disables buffering.
+        pub capacity: usize,
+        /// File further messages are appended to once the memory is full,
+        /// kept across restarts.
+        pub spool: Option<std::path::PathBuf>,
+    }
+
+    impl Default for Buffer {
+        fn default() -> Self {
+            Buffer {
+                capacity: 10_000,
+                spool: None,
+            }
+        }
+    }
+
+    /// Settings of the connection pool, the driver's default for any that
+    /// is `None`.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Pool {
+        pub max_connections: Option<u32>,
+        /// Connections kept open even when idle.
+        pub min_connections: Option<u32>,
+        /// Time a write waits for a connection before failing.
+        pub acquire_timeout_ms: Option<u64>,
+        /// Time after which an idle connection is closed, never if 0.
+        pub idle_timeout_ms: Option<u64>,
+        /// Time after which a connection is closed, never if 0.
+        pub max_lifetime_ms: Option<u64>,
+        /// Prepared statements kept per connection.
+        pub statement_cache_capacity: Option<usize>,
+    }
+
+    /// TLS of the database connections, which requires sqlx to be built
+    /// with one of its TLS features, e.g. `tls-rustls`.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(default)]
+    pub struct Ssl {
+        pub mode: SslMode,
+        /// Certificate authority the server certificate is verified with.
+        pub ca: Option<std::path::PathBuf>,
+        /// Client certificate, for servers requiring one.
+        pub cert: Option<std::path::PathBuf>,
+        /// Private key of the client certificate.
+        pub key: Option<std::path::PathBuf>,
+    }
+
+    /// Named as in MySQL, `verify_identity` being PostgreSQL's
+    /// `verify-full`.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SslMode {
+        Disabled,
+        /// TLS if the server supports it.
+        #[default]
+        Preferred,
+        /// TLS without verifying the server certificate.
+        Required,
+        /// TLS with the server certificate verified against the CA.
+        VerifyCa,
+        /// As `VerifyCa`, also checking the host name.
+        VerifyIdentity,
+    }
+
+    /// Tables polled for new rows, each published as a JSON object.
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    pub struct Publisher {
+        /// Keyed by table name.
+        pub tables: BTreeMap<String, TablePublication>,
+        #[serde(default = "default_poll_interval_ms")]
+        pub interval_ms: u64,
+        /// File the watermark of each table is kept in across restarts.
+        pub state: std::path::PathBuf,
+    }
+
+    /// How the rows of a table are found and published.
+    #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+    pub struct TablePublication {
+        /// Column whose value increases with every new row, e.g. an
+        /// auto-incremented id or an `updated_at` time. Rows sharing a time
+        /// with the last row published but committed after it are missed.
+        pub watermark: String,
+        /// Topic of each row, where `{column}` is replaced by its value,
+        /// e.g. `plant/{device}/readings`.
+        pub topic: String,
+        #[serde(default)]
+        pub qos: i32,
+        #[serde(default)]
+        pub retained: bool,
+        /// Rows read at a time.
+        #[serde(default = "default_batch_size")]
+        pub batch_size: u64,
+    }
+
+    fn default_poll_interval_ms() -> u64 {
+        1000
+    }
+
+    fn default_batch_size() -> u64 {
+        100
+    }
+
+    pub(crate) fn default_coerce_types() -> bool {
+        true
+    }
+
+    fn default_separator() -> String {
+        "_".to_string()
+    }
 }